@@ -0,0 +1,497 @@
+use crate::encoding::{ReadBitcoinExt, WriteBitcoinExt};
+use crate::services::ServiceFlags;
+use crate::{SerdeBitcoin, SerdeBitcoinError};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use getset::Getters;
+use std::fmt;
+use std::io::{Cursor, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Maximum number of addresses in a single addr or addrv2 message
+const MAX_ADDR_TO_SEND: u64 = 1000;
+
+/// Maximum length of an address in an addrv2 message
+const MAX_ADDRV2_SIZE: usize = 512;
+
+/// BIP155 network IDs
+const NETWORK_ID_IPV4: u8 = 1;
+const NETWORK_ID_IPV6: u8 = 2;
+const NETWORK_ID_TORV2: u8 = 3;
+const NETWORK_ID_TORV3: u8 = 4;
+const NETWORK_ID_I2P: u8 = 5;
+const NETWORK_ID_CJDNS: u8 = 6;
+
+fn is_ipv4_mapped_ipv6(addr: &Ipv6Addr) -> bool {
+    let segments = addr.segments();
+    segments[0..5] == [0, 0, 0, 0, 0] && segments[5] == 0xffff
+}
+
+/// Reads a 16 bytes IP address, IPv4 addresses are sent mapped into IPv6
+pub(crate) fn read_ip<R: Read + ?Sized>(reader: &mut R) -> Result<IpAddr, SerdeBitcoinError> {
+    let ip: Ipv6Addr = reader.read_u128::<BigEndian>()?.into();
+    if is_ipv4_mapped_ipv6(&ip) {
+        Ok(IpAddr::V4(
+            ip.to_ipv4_mapped()
+                .ok_or(SerdeBitcoinError::FailedToMapToIpv4)?,
+        ))
+    } else {
+        Ok(IpAddr::V6(ip))
+    }
+}
+
+/// Writes a 16 bytes IP address, IPv4 addresses are sent mapped into IPv6
+pub(crate) fn write_ip<W: Write + ?Sized>(
+    writer: &mut W,
+    ip: &IpAddr,
+) -> Result<(), SerdeBitcoinError> {
+    writer.write_u128::<BigEndian>(u128::from_be_bytes(
+        match ip {
+            IpAddr::V4(x) => x.to_ipv6_mapped(),
+            IpAddr::V6(x) => *x,
+        }
+        .octets(),
+    ))?;
+    Ok(())
+}
+
+/// Reads the number of entries of an address list, refusing lists longer than allowed
+fn read_address_count<R: Read + ?Sized>(reader: &mut R) -> Result<usize, SerdeBitcoinError> {
+    let count = reader.read_compact_size()?;
+    if count > MAX_ADDR_TO_SEND {
+        return Err(SerdeBitcoinError::TooManyAddresses(count));
+    }
+
+    usize::try_from(count).map_err(SerdeBitcoinError::InvalidPayloadLength)
+}
+
+/// Address entry of an addr message
+#[derive(Getters, Debug, Clone, PartialEq)]
+pub struct NetAddr {
+    #[getset(get = "pub")]
+    time: u32,
+
+    #[getset(get = "pub")]
+    services: ServiceFlags,
+
+    #[getset(get = "pub")]
+    address: SocketAddr,
+}
+
+impl NetAddr {
+    pub fn new(time: u32, services: ServiceFlags, address: SocketAddr) -> Self {
+        Self {
+            time,
+            services,
+            address,
+        }
+    }
+
+    fn read<R: Read + ?Sized>(reader: &mut R) -> Result<Self, SerdeBitcoinError> {
+        let time = reader.read_u32::<LittleEndian>()?;
+        let services = ServiceFlags::from_bits_retain(reader.read_u64::<LittleEndian>()?);
+        let ip = read_ip(reader)?;
+        let port = reader.read_u16::<BigEndian>()?;
+
+        Ok(Self {
+            time,
+            services,
+            address: SocketAddr::new(ip, port),
+        })
+    }
+
+    fn write<W: Write + ?Sized>(&self, writer: &mut W) -> Result<(), SerdeBitcoinError> {
+        writer.write_u32::<LittleEndian>(self.time)?;
+        writer.write_u64::<LittleEndian>(self.services.bits())?;
+        write_ip(writer, &self.address.ip())?;
+        writer.write_u16::<BigEndian>(self.address.port())?;
+        Ok(())
+    }
+}
+
+#[derive(Getters, Debug, Default, PartialEq)]
+pub struct Addr {
+    #[getset(get = "pub")]
+    addresses: Vec<NetAddr>,
+}
+
+impl Addr {
+    pub fn new(addresses: Vec<NetAddr>) -> Self {
+        Self { addresses }
+    }
+}
+
+impl SerdeBitcoin for Addr {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::with_capacity(9 + self.addresses.len() * 30);
+        result.write_compact_size(self.addresses.len() as u64)?;
+        for address in &self.addresses {
+            address.write(&mut result)?;
+        }
+
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<Addr, SerdeBitcoinError> {
+        let mut cursor = Cursor::new(data);
+        let count = read_address_count(&mut cursor)?;
+        let addresses = (0..count)
+            .map(|_| NetAddr::read(&mut cursor))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Addr { addresses })
+    }
+}
+
+/// Address of any of the networks supported by addrv2 (BIP155)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NetworkAddress {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    TorV2([u8; 10]),
+    TorV3([u8; 32]),
+    I2p([u8; 32]),
+    Cjdns(Ipv6Addr),
+    /// Network ID not known, kept as is
+    Unknown(u8, Vec<u8>),
+}
+
+impl NetworkAddress {
+    fn network_id(&self) -> u8 {
+        match self {
+            NetworkAddress::Ipv4(_) => NETWORK_ID_IPV4,
+            NetworkAddress::Ipv6(_) => NETWORK_ID_IPV6,
+            NetworkAddress::TorV2(_) => NETWORK_ID_TORV2,
+            NetworkAddress::TorV3(_) => NETWORK_ID_TORV3,
+            NetworkAddress::I2p(_) => NETWORK_ID_I2P,
+            NetworkAddress::Cjdns(_) => NETWORK_ID_CJDNS,
+            NetworkAddress::Unknown(network_id, _) => *network_id,
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            NetworkAddress::Ipv4(ip) => ip.octets().to_vec(),
+            NetworkAddress::Ipv6(ip) | NetworkAddress::Cjdns(ip) => ip.octets().to_vec(),
+            NetworkAddress::TorV2(bytes) => bytes.to_vec(),
+            NetworkAddress::TorV3(bytes) | NetworkAddress::I2p(bytes) => bytes.to_vec(),
+            NetworkAddress::Unknown(_, bytes) => bytes.clone(),
+        }
+    }
+
+    fn from_bytes(network_id: u8, bytes: Vec<u8>) -> Result<Self, SerdeBitcoinError> {
+        let invalid_length =
+            |bytes: Vec<u8>| SerdeBitcoinError::InvalidAddressLength(network_id, bytes.len());
+
+        Ok(match network_id {
+            NETWORK_ID_IPV4 => {
+                NetworkAddress::Ipv4(<[u8; 4]>::try_from(bytes).map_err(invalid_length)?.into())
+            }
+            NETWORK_ID_IPV6 => {
+                NetworkAddress::Ipv6(<[u8; 16]>::try_from(bytes).map_err(invalid_length)?.into())
+            }
+            NETWORK_ID_TORV2 => NetworkAddress::TorV2(bytes.try_into().map_err(invalid_length)?),
+            NETWORK_ID_TORV3 => NetworkAddress::TorV3(bytes.try_into().map_err(invalid_length)?),
+            NETWORK_ID_I2P => NetworkAddress::I2p(bytes.try_into().map_err(invalid_length)?),
+            NETWORK_ID_CJDNS => {
+                NetworkAddress::Cjdns(<[u8; 16]>::try_from(bytes).map_err(invalid_length)?.into())
+            }
+            network_id => NetworkAddress::Unknown(network_id, bytes),
+        })
+    }
+
+    /// IP address, if the address belongs to the IPv4 or IPv6 networks
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            NetworkAddress::Ipv4(ip) => Some(IpAddr::V4(*ip)),
+            NetworkAddress::Ipv6(ip) => Some(IpAddr::V6(*ip)),
+            _ => None,
+        }
+    }
+}
+
+impl From<IpAddr> for NetworkAddress {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => NetworkAddress::Ipv4(ip),
+            IpAddr::V6(ip) => NetworkAddress::Ipv6(ip),
+        }
+    }
+}
+
+impl fmt::Display for NetworkAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkAddress::Ipv4(ip) => write!(f, "{ip}"),
+            NetworkAddress::Ipv6(ip) => write!(f, "[{ip}]"),
+            NetworkAddress::TorV2(bytes) => write!(f, "torv2:{}", hex::encode(bytes)),
+            NetworkAddress::TorV3(bytes) => write!(f, "torv3:{}", hex::encode(bytes)),
+            NetworkAddress::I2p(bytes) => write!(f, "i2p:{}", hex::encode(bytes)),
+            NetworkAddress::Cjdns(ip) => write!(f, "cjdns:[{ip}]"),
+            NetworkAddress::Unknown(network_id, bytes) => {
+                write!(f, "unknown({network_id}):{}", hex::encode(bytes))
+            }
+        }
+    }
+}
+
+/// Address entry of an addrv2 message
+#[derive(Getters, Debug, Clone, PartialEq)]
+pub struct NetAddrV2 {
+    #[getset(get = "pub")]
+    time: u32,
+
+    #[getset(get = "pub")]
+    services: ServiceFlags,
+
+    #[getset(get = "pub")]
+    address: NetworkAddress,
+
+    #[getset(get = "pub")]
+    port: u16,
+}
+
+impl NetAddrV2 {
+    pub fn new(time: u32, services: ServiceFlags, address: NetworkAddress, port: u16) -> Self {
+        Self {
+            time,
+            services,
+            address,
+            port,
+        }
+    }
+
+    /// Socket address, if the address belongs to the IPv4 or IPv6 networks
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        self.address.ip().map(|ip| SocketAddr::new(ip, self.port))
+    }
+
+    fn read<R: Read + ?Sized>(reader: &mut R) -> Result<Self, SerdeBitcoinError> {
+        let time = reader.read_u32::<LittleEndian>()?;
+        let services = ServiceFlags::from_bits_retain(reader.read_compact_size()?);
+        let network_id = reader.read_u8()?;
+        let bytes = reader.read_var_bytes()?;
+        if bytes.len() > MAX_ADDRV2_SIZE {
+            return Err(SerdeBitcoinError::InvalidAddressLength(
+                network_id,
+                bytes.len(),
+            ));
+        }
+        let address = NetworkAddress::from_bytes(network_id, bytes)?;
+        let port = reader.read_u16::<BigEndian>()?;
+
+        Ok(Self {
+            time,
+            services,
+            address,
+            port,
+        })
+    }
+
+    fn write<W: Write + ?Sized>(&self, writer: &mut W) -> Result<(), SerdeBitcoinError> {
+        writer.write_u32::<LittleEndian>(self.time)?;
+        writer.write_compact_size(self.services.bits())?;
+        writer.write_u8(self.address.network_id())?;
+        writer.write_var_bytes(&self.address.bytes())?;
+        writer.write_u16::<BigEndian>(self.port)?;
+        Ok(())
+    }
+}
+
+impl From<NetAddr> for NetAddrV2 {
+    fn from(net_addr: NetAddr) -> Self {
+        Self {
+            time: net_addr.time,
+            services: net_addr.services,
+            address: net_addr.address.ip().into(),
+            port: net_addr.address.port(),
+        }
+    }
+}
+
+impl fmt::Display for NetAddrV2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.address, self.port)
+    }
+}
+
+/// Address list supporting the networks introduced in BIP155
+#[derive(Getters, Debug, Default, PartialEq)]
+pub struct AddrV2 {
+    #[getset(get = "pub")]
+    addresses: Vec<NetAddrV2>,
+}
+
+impl AddrV2 {
+    pub fn new(addresses: Vec<NetAddrV2>) -> Self {
+        Self { addresses }
+    }
+}
+
+impl SerdeBitcoin for AddrV2 {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::new();
+        result.write_compact_size(self.addresses.len() as u64)?;
+        for address in &self.addresses {
+            address.write(&mut result)?;
+        }
+
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<AddrV2, SerdeBitcoinError> {
+        let mut cursor = Cursor::new(data);
+        let count = read_address_count(&mut cursor)?;
+        let addresses = (0..count)
+            .map(|_| NetAddrV2::read(&mut cursor))
+            .collect::<Result<_, _>>()?;
+
+        Ok(AddrV2 { addresses })
+    }
+}
+
+/// Requests the peer's known addresses, it has no payload
+#[derive(Debug, PartialEq)]
+pub struct GetAddr;
+
+impl SerdeBitcoin for GetAddr {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        Ok(vec![])
+    }
+
+    fn deserialize(_data: &mut [u8]) -> Result<GetAddr, SerdeBitcoinError> {
+        Ok(GetAddr {})
+    }
+}
+
+/// Signals support for addrv2 messages, it must be sent between version and verack
+#[derive(Debug, PartialEq)]
+pub struct SendAddrV2;
+
+impl SerdeBitcoin for SendAddrV2 {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        Ok(vec![])
+    }
+
+    fn deserialize(_data: &mut [u8]) -> Result<SendAddrV2, SerdeBitcoinError> {
+        Ok(SendAddrV2 {})
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_addr() {
+        // Create an Addr
+        let addr = Addr::new(vec![
+            NetAddr::new(
+                1,
+                ServiceFlags::NODE_NETWORK,
+                "127.0.0.1:18333".parse::<SocketAddr>().unwrap(),
+            ),
+            NetAddr::new(
+                2,
+                ServiceFlags::NODE_NETWORK | ServiceFlags::NODE_WITNESS,
+                "[2001:db8::1]:8333".parse::<SocketAddr>().unwrap(),
+            ),
+        ]);
+
+        // Serialize the Addr into a Vec<u8>
+        let mut serialized_bytes = addr.serialize().expect("serialize");
+
+        // Assert that the serialized bytes length is as expected
+        assert_eq!(serialized_bytes.len(), 1 + 2 * 30);
+
+        // Deserialize the bytes back to Addr
+        let deserialized: Addr =
+            Addr::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, addr);
+    }
+
+    #[test]
+    fn test_addrv2() {
+        // Create an AddrV2 with every network
+        let addr = AddrV2::new(vec![
+            NetAddrV2::new(
+                1,
+                ServiceFlags::NODE_NETWORK,
+                NetworkAddress::Ipv4(Ipv4Addr::LOCALHOST),
+                8333,
+            ),
+            NetAddrV2::new(
+                1,
+                ServiceFlags::NODE_NETWORK,
+                NetworkAddress::Ipv6(Ipv6Addr::LOCALHOST),
+                8333,
+            ),
+            NetAddrV2::new(
+                1,
+                ServiceFlags::NODE_NETWORK,
+                NetworkAddress::TorV2([1; 10]),
+                8333,
+            ),
+            NetAddrV2::new(
+                1,
+                ServiceFlags::NODE_NETWORK,
+                NetworkAddress::TorV3([2; 32]),
+                8333,
+            ),
+            NetAddrV2::new(
+                1,
+                ServiceFlags::NODE_NETWORK,
+                NetworkAddress::I2p([3; 32]),
+                0,
+            ),
+            NetAddrV2::new(
+                1,
+                ServiceFlags::NODE_NETWORK,
+                NetworkAddress::Cjdns("fc00::1".parse().unwrap()),
+                8333,
+            ),
+            NetAddrV2::new(
+                1,
+                // Bits without a name survive the round trip
+                ServiceFlags::from_bits_retain(1 << 24 | 1),
+                NetworkAddress::Unknown(42, vec![4; 7]),
+                8333,
+            ),
+        ]);
+
+        // Serialize the AddrV2 into a Vec<u8>
+        let mut serialized_bytes = addr.serialize().expect("serialize");
+
+        // Deserialize the bytes back to AddrV2
+        let deserialized: AddrV2 =
+            AddrV2::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, addr);
+    }
+
+    #[test]
+    fn test_addrv2_invalid_length() {
+        // IPv4 network ID with 5 bytes of address
+        let mut serialized_bytes = vec![1, 0, 0, 0, 0, 1, NETWORK_ID_IPV4, 5, 1, 2, 3, 4, 5, 0, 0];
+
+        assert!(matches!(
+            AddrV2::deserialize(serialized_bytes.as_mut_slice()),
+            Err(SerdeBitcoinError::InvalidAddressLength(NETWORK_ID_IPV4, 5))
+        ));
+    }
+
+    #[test]
+    fn test_too_many_addresses() {
+        let mut serialized_bytes = Vec::new();
+        serialized_bytes
+            .write_compact_size(MAX_ADDR_TO_SEND + 1)
+            .expect("serialize");
+
+        assert!(matches!(
+            Addr::deserialize(serialized_bytes.as_mut_slice()),
+            Err(SerdeBitcoinError::TooManyAddresses(_))
+        ));
+    }
+}
@@ -0,0 +1,381 @@
+use crate::block_header::BlockHeader;
+use crate::encoding::{compact_size_len, ReadBitcoinExt, WriteBitcoinExt};
+use crate::hash::Hash256;
+use crate::network::Network;
+use crate::transaction::Transaction;
+use crate::{SerdeBitcoin, SerdeBitcoinError};
+use getset::Getters;
+use std::io::Cursor;
+
+/// Maximum block weight (BIP141)
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+
+/// OP_RETURN, push of 36 bytes and the commitment header 0xaa21a9ed (BIP141)
+const WITNESS_COMMITMENT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// Length of the script_pubkey prefix plus the 32 bytes commitment
+const WITNESS_COMMITMENT_LENGTH: usize = 38;
+
+/// Computes the merkle root of a list of hashes.
+///
+/// The last hash of a level is paired with itself when the level has an odd length. The second
+/// value is true if two identical hashes were paired, a tree that can be mutated without changing
+/// its root (CVE-2012-2459).
+pub fn merkle_root(hashes: &[Hash256]) -> (Hash256, bool) {
+    if hashes.is_empty() {
+        return (Hash256::ZERO, false);
+    }
+
+    let mut level = hashes.to_vec();
+    let mut mutated = false;
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let left = pair[0];
+                let right = pair.get(1).copied().unwrap_or(left);
+                if pair.len() == 2 && left == right {
+                    mutated = true;
+                }
+
+                let mut data = [0u8; 64];
+                data[..32].copy_from_slice(left.as_bytes());
+                data[32..].copy_from_slice(right.as_bytes());
+                Hash256::hash(&data)
+            })
+            .collect();
+    }
+
+    (level[0], mutated)
+}
+
+#[derive(Getters, Debug, Clone, PartialEq)]
+pub struct Block {
+    #[getset(get = "pub")]
+    header: BlockHeader,
+
+    #[getset(get = "pub")]
+    transactions: Vec<Transaction>,
+}
+
+impl Block {
+    pub fn new(header: BlockHeader, transactions: Vec<Transaction>) -> Self {
+        Self {
+            header,
+            transactions,
+        }
+    }
+
+    pub fn block_hash(&self) -> Hash256 {
+        self.header.block_hash()
+    }
+
+    /// Merkle root of the txids, the second value flags a mutated tree
+    pub fn compute_merkle_root(&self) -> (Hash256, bool) {
+        let txids: Vec<Hash256> = self.transactions.iter().map(Transaction::txid).collect();
+        merkle_root(&txids)
+    }
+
+    /// Merkle root of the wtxids, where the coinbase counts as zero
+    pub fn compute_witness_root(&self) -> Hash256 {
+        let wtxids: Vec<Hash256> = self
+            .transactions
+            .iter()
+            .enumerate()
+            .map(|(i, tx)| if i == 0 { Hash256::ZERO } else { tx.wtxid() })
+            .collect();
+        merkle_root(&wtxids).0
+    }
+
+    /// Commitment found in the last matching output of the coinbase, if any
+    pub fn witness_commitment(&self) -> Option<Hash256> {
+        let coinbase = self.transactions.first()?;
+        coinbase
+            .outputs()
+            .iter()
+            .rev()
+            .map(|output| output.script_pubkey())
+            .find(|script| {
+                script.len() >= WITNESS_COMMITMENT_LENGTH
+                    && script.starts_with(&WITNESS_COMMITMENT_PREFIX)
+            })
+            .map(|script| {
+                let mut commitment = [0u8; 32];
+                commitment.copy_from_slice(
+                    &script[WITNESS_COMMITMENT_PREFIX.len()..WITNESS_COMMITMENT_LENGTH],
+                );
+                Hash256::new(commitment)
+            })
+    }
+
+    /// Size in bytes without the witness data
+    pub fn stripped_size(&self) -> usize {
+        self.size_with(Transaction::base_size)
+    }
+
+    /// Size in bytes with the witness data, as sent on the wire
+    pub fn total_size(&self) -> usize {
+        self.size_with(Transaction::total_size)
+    }
+
+    /// Weight units as defined by BIP141
+    pub fn weight(&self) -> usize {
+        self.stripped_size() * 3 + self.total_size()
+    }
+
+    fn size_with(&self, tx_size: fn(&Transaction) -> usize) -> usize {
+        BlockHeader::SIZE
+            + compact_size_len(self.transactions.len() as u64)
+            + self.transactions.iter().map(tx_size).sum::<usize>()
+    }
+
+    /// Checks the proof of work, the merkle root, the witness commitment and the weight
+    pub fn validate(&self, network: &Network) -> Result<(), SerdeBitcoinError> {
+        self.header.check_proof_of_work(network)?;
+
+        if self.transactions.is_empty() {
+            return Err(SerdeBitcoinError::EmptyBlock);
+        }
+
+        let (merkle_root, mutated) = self.compute_merkle_root();
+        if merkle_root != *self.header.merkle_root() {
+            return Err(SerdeBitcoinError::InvalidMerkleRoot(
+                *self.header.merkle_root(),
+                merkle_root,
+            ));
+        }
+        if mutated {
+            return Err(SerdeBitcoinError::MutatedMerkleTree);
+        }
+
+        self.check_witness_commitment()?;
+
+        let weight = self.weight();
+        if weight > MAX_BLOCK_WEIGHT {
+            return Err(SerdeBitcoinError::BlockTooHeavy(weight));
+        }
+
+        Ok(())
+    }
+
+    /// Blocks without a witness commitment can not carry witness data, otherwise the coinbase
+    /// witness holds the reserved value hashed together with the witness root
+    fn check_witness_commitment(&self) -> Result<(), SerdeBitcoinError> {
+        let Some(commitment) = self.witness_commitment() else {
+            return match self.transactions.iter().find(|tx| tx.has_witness()) {
+                Some(tx) => Err(SerdeBitcoinError::UnexpectedWitness(tx.txid())),
+                None => Ok(()),
+            };
+        };
+
+        let reserved_value = match self.transactions[0].inputs().as_slice() {
+            [input] => match input.witness().as_slice() {
+                [value] if value.len() == 32 => value,
+                _ => return Err(SerdeBitcoinError::InvalidWitnessReservedValue),
+            },
+            _ => return Err(SerdeBitcoinError::InvalidWitnessReservedValue),
+        };
+
+        let mut data = [0u8; 64];
+        data[..32].copy_from_slice(self.compute_witness_root().as_bytes());
+        data[32..].copy_from_slice(reserved_value);
+        if Hash256::hash(&data) != commitment {
+            return Err(SerdeBitcoinError::InvalidWitnessCommitment);
+        }
+
+        Ok(())
+    }
+}
+
+impl SerdeBitcoin for Block {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::with_capacity(self.total_size());
+        self.header.write(&mut result)?;
+        result.write_compact_size(self.transactions.len() as u64)?;
+        for tx in &self.transactions {
+            tx.write(&mut result, true)?;
+        }
+
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<Block, SerdeBitcoinError> {
+        let mut cursor = Cursor::new(data);
+        let header = BlockHeader::read(&mut cursor)?;
+        let count = cursor.read_compact_size()?;
+        let transactions = (0..count)
+            .map(|_| Transaction::read(&mut cursor))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Block {
+            header,
+            transactions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::GENESIS_COINBASE;
+    use crate::transaction::{OutPoint, TxIn, TxOut};
+
+    fn genesis_block() -> Block {
+        let mut coinbase = hex::decode(GENESIS_COINBASE).unwrap();
+        Block::new(
+            Network::Mainnet.genesis_block_header(),
+            vec![Transaction::deserialize(coinbase.as_mut_slice()).expect("coinbase")],
+        )
+    }
+
+    /// Builds a regtest block on top of the genesis with a valid merkle root and proof of work
+    fn mine_block(transactions: Vec<Transaction>) -> Block {
+        let network = Network::Regtest;
+        let genesis = network.genesis_block_header();
+        let (merkle_root, _) = merkle_root(
+            &transactions
+                .iter()
+                .map(Transaction::txid)
+                .collect::<Vec<_>>(),
+        );
+        let header = (0..)
+            .map(|nonce| {
+                BlockHeader::new(
+                    4,
+                    genesis.block_hash(),
+                    merkle_root,
+                    genesis.time() + 600,
+                    network.pow_limit_bits(),
+                    nonce,
+                )
+            })
+            .find(|header| header.check_proof_of_work(&network).is_ok())
+            .expect("nonce");
+        Block::new(header, transactions)
+    }
+
+    fn segwit_transaction(witness: Vec<u8>) -> Transaction {
+        Transaction::new(
+            2,
+            vec![TxIn::new(
+                OutPoint::new(Hash256::new([1; 32]), 0),
+                Vec::new(),
+                0xffff_ffff,
+                vec![witness],
+            )],
+            vec![TxOut::new(1000, vec![0x51])],
+            0,
+        )
+    }
+
+    /// Coinbase committing to `witness_root` with a zero reserved value
+    fn coinbase_with_commitment(witness_root: Hash256) -> Transaction {
+        let mut data = [0u8; 64];
+        data[..32].copy_from_slice(witness_root.as_bytes());
+        let mut script_pubkey = WITNESS_COMMITMENT_PREFIX.to_vec();
+        script_pubkey.extend(Hash256::hash(&data).as_bytes());
+
+        Transaction::new(
+            2,
+            vec![TxIn::new(
+                OutPoint::new(Hash256::ZERO, 0xffff_ffff),
+                vec![0x51, 0x00],
+                0xffff_ffff,
+                vec![vec![0; 32]],
+            )],
+            vec![TxOut::new(0, script_pubkey)],
+            0,
+        )
+    }
+
+    #[test]
+    fn test_block() {
+        let block = genesis_block();
+
+        // Serialize the Block into a Vec<u8>
+        let mut serialized_bytes = block.serialize().expect("serialize");
+
+        // Assert that the serialized bytes length is the well known genesis block size
+        assert_eq!(serialized_bytes.len(), 285);
+        assert_eq!(block.total_size(), 285);
+        assert_eq!(block.weight(), 4 * 285);
+
+        // Deserialize the bytes back to Block
+        let deserialized =
+            Block::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, block);
+
+        // Assert that the genesis block is valid
+        block.validate(&Network::Mainnet).expect("valid block");
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let a = Hash256::new([1; 32]);
+        let b = Hash256::new([2; 32]);
+        let c = Hash256::new([3; 32]);
+        let pair = |left: Hash256, right: Hash256| {
+            Hash256::hash(&[left.as_bytes().as_slice(), right.as_bytes()].concat())
+        };
+
+        assert_eq!(merkle_root(&[a]), (a, false));
+        assert_eq!(merkle_root(&[a, b]), (pair(a, b), false));
+        assert_eq!(
+            merkle_root(&[a, b, c]),
+            (pair(pair(a, b), pair(c, c)), false)
+        );
+
+        // Duplicating the last hash gives the same root but flags the tree as mutated
+        assert_eq!(
+            merkle_root(&[a, b, c, c]),
+            (pair(pair(a, b), pair(c, c)), true)
+        );
+    }
+
+    #[test]
+    fn test_invalid_merkle_root() {
+        let block = genesis_block();
+        let coinbase = &block.transactions()[0];
+        let coinbase = Transaction::new(
+            *coinbase.version(),
+            coinbase.inputs().clone(),
+            coinbase.outputs().clone(),
+            1,
+        );
+        let block = Block::new(block.header().clone(), vec![coinbase]);
+
+        assert!(matches!(
+            block.validate(&Network::Mainnet),
+            Err(SerdeBitcoinError::InvalidMerkleRoot(..))
+        ));
+    }
+
+    #[test]
+    fn test_witness_commitment() {
+        let tx = segwit_transaction(vec![0xab; 10]);
+        let (witness_root, _) = merkle_root(&[Hash256::ZERO, tx.wtxid()]);
+        let block = mine_block(vec![coinbase_with_commitment(witness_root), tx.clone()]);
+
+        // Assert that the commitment matches the witness data
+        block.validate(&Network::Regtest).expect("valid block");
+        assert!(block.weight() < 4 * block.total_size());
+
+        // Assert that other witness data does not match the commitment
+        let other = segwit_transaction(vec![0xcd; 10]);
+        let block = mine_block(vec![coinbase_with_commitment(witness_root), other]);
+        assert!(matches!(
+            block.validate(&Network::Regtest),
+            Err(SerdeBitcoinError::InvalidWitnessCommitment)
+        ));
+
+        // Assert that witness data requires a commitment
+        let coinbase = genesis_block().transactions()[0].clone();
+        let block = mine_block(vec![coinbase, tx]);
+        assert!(matches!(
+            block.validate(&Network::Regtest),
+            Err(SerdeBitcoinError::UnexpectedWitness(_))
+        ));
+    }
+}
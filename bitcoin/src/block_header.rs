@@ -0,0 +1,363 @@
+use crate::encoding::{ReadBitcoinExt, WriteBitcoinExt};
+use crate::hash::Hash256;
+use crate::network::Network;
+use crate::{SerdeBitcoin, SerdeBitcoinError};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use getset::Getters;
+use std::io::{Cursor, Read, Write};
+
+/// 256 bits target stored big-endian, so the derived ordering is the numeric one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Target([u8; 32]);
+
+impl Target {
+    /// Decodes the compact representation used by the `bits` field of the header
+    pub fn from_compact(bits: u32) -> Result<Self, SerdeBitcoinError> {
+        let exponent = bits >> 24;
+        let mantissa = bits & 0x007f_ffff;
+
+        if mantissa != 0 && bits & 0x0080_0000 != 0 {
+            return Err(SerdeBitcoinError::InvalidTarget(bits));
+        }
+
+        let mut target = [0u8; 32];
+        if exponent <= 3 {
+            let value = mantissa >> (8 * (3 - exponent));
+            target[28..].copy_from_slice(&value.to_be_bytes());
+        } else {
+            // Least significant byte of the mantissa first
+            for (i, byte) in mantissa.to_le_bytes()[..3].iter().enumerate() {
+                let position = (exponent - 3) as usize + i;
+                if position < target.len() {
+                    target[target.len() - 1 - position] = *byte;
+                } else if *byte != 0 {
+                    return Err(SerdeBitcoinError::InvalidTarget(bits));
+                }
+            }
+        }
+
+        // No hash can meet a zero target
+        if target == [0u8; 32] {
+            return Err(SerdeBitcoinError::InvalidTarget(bits));
+        }
+
+        Ok(Self(target))
+    }
+
+    /// Checks if `hash`, read as a little-endian number, is at or below the target
+    pub fn is_met_by(&self, hash: &Hash256) -> bool {
+        let mut hash = *hash.as_bytes();
+        hash.reverse();
+        hash <= self.0
+    }
+
+    /// Expected number of hashes to meet the target, 2^256 / (target + 1)
+    pub fn work(&self) -> Work {
+        let mut target = [0u64; 4];
+        for (i, limb) in target.iter_mut().enumerate() {
+            let end = self.0.len() - 8 * i;
+            *limb = u64::from_be_bytes(self.0[end - 8..end].try_into().expect("8 bytes"));
+        }
+
+        // Computed as ~target / (target + 1) + 1 like Bitcoin Core, 2^256 does not fit
+        let not_target = target.map(|limb| !limb);
+        let (divisor, overflow) = add(&target, &[1, 0, 0, 0]);
+        if overflow {
+            return Work([1, 0, 0, 0]);
+        }
+        Work(add(&divide(&not_target, &divisor), &[1, 0, 0, 0]).0)
+    }
+}
+
+/// Expected number of hashes behind a header or a chain, 256 bits as little-endian limbs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Work([u64; 4]);
+
+impl Work {
+    /// Saturates instead of overflowing, no real chain gets close
+    pub fn saturating_add(&self, other: &Work) -> Work {
+        match add(&self.0, &other.0) {
+            (sum, false) => Work(sum),
+            (_, true) => Work([u64::MAX; 4]),
+        }
+    }
+}
+
+impl Ord for Work {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for Work {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Adds two 256 bits numbers, returning whether it overflowed
+fn add(a: &[u64; 4], b: &[u64; 4]) -> ([u64; 4], bool) {
+    let mut sum = [0u64; 4];
+    let mut carry = false;
+    for i in 0..4 {
+        let (partial, first) = a[i].overflowing_add(b[i]);
+        let (partial, second) = partial.overflowing_add(carry.into());
+        sum[i] = partial;
+        carry = first || second;
+    }
+    (sum, carry)
+}
+
+/// Long division of two 256 bits numbers, `divisor` is never zero
+fn divide(dividend: &[u64; 4], divisor: &[u64; 4]) -> [u64; 4] {
+    let mut quotient = [0u64; 4];
+    let mut remainder = [0u64; 4];
+    for bit in (0..256).rev() {
+        // Shift the next bit of the dividend into the remainder
+        let carry = remainder[3] >> 63 == 1;
+        for i in (1..4).rev() {
+            remainder[i] = remainder[i] << 1 | remainder[i - 1] >> 63;
+        }
+        remainder[0] = remainder[0] << 1 | (dividend[bit / 64] >> (bit % 64)) & 1;
+
+        // A carry means the remainder went over 2^256, above any divisor
+        if carry || remainder.iter().rev().cmp(divisor.iter().rev()).is_ge() {
+            let mut borrow = false;
+            for i in 0..4 {
+                let (partial, first) = remainder[i].overflowing_sub(divisor[i]);
+                let (partial, second) = partial.overflowing_sub(borrow.into());
+                remainder[i] = partial;
+                borrow = first || second;
+            }
+            quotient[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+    quotient
+}
+
+#[derive(Getters, Debug, Clone, PartialEq)]
+pub struct BlockHeader {
+    #[getset(get = "pub")]
+    version: i32,
+
+    #[getset(get = "pub")]
+    prev_block_hash: Hash256,
+
+    #[getset(get = "pub")]
+    merkle_root: Hash256,
+
+    #[getset(get = "pub")]
+    time: u32,
+
+    #[getset(get = "pub")]
+    bits: u32,
+
+    #[getset(get = "pub")]
+    nonce: u32,
+}
+
+impl BlockHeader {
+    pub const SIZE: usize = 80;
+
+    pub fn new(
+        version: i32,
+        prev_block_hash: Hash256,
+        merkle_root: Hash256,
+        time: u32,
+        bits: u32,
+        nonce: u32,
+    ) -> Self {
+        Self {
+            version,
+            prev_block_hash,
+            merkle_root,
+            time,
+            bits,
+            nonce,
+        }
+    }
+
+    /// Double SHA256 of the serialized header
+    pub fn block_hash(&self) -> Hash256 {
+        let mut data = Vec::with_capacity(BlockHeader::SIZE);
+        self.write(&mut data).expect("Writing to a Vec never fails");
+        Hash256::hash(&data)
+    }
+
+    pub fn target(&self) -> Result<Target, SerdeBitcoinError> {
+        Target::from_compact(self.bits)
+    }
+
+    /// Checks that the target is within the network limit and that the hash meets it
+    pub fn check_proof_of_work(&self, network: &Network) -> Result<(), SerdeBitcoinError> {
+        let target = self.target()?;
+        if target > Target::from_compact(network.pow_limit_bits())? {
+            return Err(SerdeBitcoinError::InvalidTarget(self.bits));
+        }
+
+        let block_hash = self.block_hash();
+        if !target.is_met_by(&block_hash) {
+            return Err(SerdeBitcoinError::InsufficientProofOfWork(block_hash));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn read<R: Read + ?Sized>(reader: &mut R) -> Result<Self, SerdeBitcoinError> {
+        Ok(Self {
+            version: reader.read_i32::<LittleEndian>()?,
+            prev_block_hash: reader.read_hash()?,
+            merkle_root: reader.read_hash()?,
+            time: reader.read_u32::<LittleEndian>()?,
+            bits: reader.read_u32::<LittleEndian>()?,
+            nonce: reader.read_u32::<LittleEndian>()?,
+        })
+    }
+
+    pub(crate) fn write<W: Write + ?Sized>(&self, writer: &mut W) -> Result<(), SerdeBitcoinError> {
+        writer.write_i32::<LittleEndian>(self.version)?;
+        writer.write_hash(&self.prev_block_hash)?;
+        writer.write_hash(&self.merkle_root)?;
+        writer.write_u32::<LittleEndian>(self.time)?;
+        writer.write_u32::<LittleEndian>(self.bits)?;
+        writer.write_u32::<LittleEndian>(self.nonce)?;
+        Ok(())
+    }
+}
+
+impl SerdeBitcoin for BlockHeader {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::with_capacity(BlockHeader::SIZE);
+        self.write(&mut result)?;
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<BlockHeader, SerdeBitcoinError> {
+        BlockHeader::read(&mut Cursor::new(data))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_block_header() {
+        let header = Network::Mainnet.genesis_block_header();
+
+        // Serialize the BlockHeader into a Vec<u8>
+        let mut serialized_bytes = header.serialize().expect("serialize");
+
+        // Assert that the serialized bytes length is as expected
+        assert_eq!(serialized_bytes.len(), BlockHeader::SIZE);
+
+        // Deserialize the bytes back to BlockHeader
+        let deserialized: BlockHeader =
+            BlockHeader::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, header);
+    }
+
+    #[test]
+    fn test_genesis_block_hash() {
+        for (network, expected) in [
+            (
+                Network::Mainnet,
+                "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+            ),
+            (
+                Network::Testnet,
+                "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+            ),
+            (
+                Network::Testnet4,
+                "00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043",
+            ),
+            (
+                Network::Signet,
+                "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6",
+            ),
+            (
+                Network::Regtest,
+                "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+            ),
+        ] {
+            let header = network.genesis_block_header();
+
+            // Assert that the hash matches the well known genesis hash
+            assert_eq!(header.block_hash().to_string(), expected);
+
+            // Assert that the genesis block has a valid proof of work
+            header.check_proof_of_work(&network).expect("proof of work");
+        }
+    }
+
+    #[test]
+    fn test_target_from_compact() {
+        let target = Target::from_compact(0x1d00ffff).expect("target");
+        let mut expected = [0u8; 32];
+        expected[4] = 0xff;
+        expected[5] = 0xff;
+        assert_eq!(target, Target(expected));
+
+        // Small exponent shifts the mantissa to the right
+        let target = Target::from_compact(0x0200_8000).expect("target");
+        let mut expected = [0u8; 32];
+        expected[31] = 0x80;
+        assert_eq!(target, Target(expected));
+
+        // Negative targets are invalid
+        assert!(Target::from_compact(0x0380_0001).is_err());
+
+        // Targets above 256 bits are invalid
+        assert!(Target::from_compact(0x2201_0000).is_err());
+    }
+
+    #[test]
+    fn test_zero_target() {
+        // Zero targets are invalid, whether the mantissa is zero or shifted out
+        for bits in [0x1d00_0000, 0x1d80_0000, 0x0100_ffff] {
+            assert!(matches!(
+                Target::from_compact(bits),
+                Err(SerdeBitcoinError::InvalidTarget(b)) if b == bits
+            ));
+        }
+    }
+
+    #[test]
+    fn test_work() {
+        // Same values as Bitcoin Core's GetBlockProof
+        let work = Target::from_compact(0x1d00ffff).expect("target").work();
+        assert_eq!(work, Work([0x0001_0001_0001, 0, 0, 0]));
+        let work = Target::from_compact(0x207fffff).expect("target").work();
+        assert_eq!(work, Work([2, 0, 0, 0]));
+
+        // A lower target needs more work
+        let harder = Target::from_compact(0x1b04864c).expect("target").work();
+        assert!(harder > Work([0x0001_0001_0001, 0, 0, 0]));
+        assert_eq!(harder, Work([0x3894_6224_e37e, 0, 0, 0]));
+        assert_eq!(
+            harder.saturating_add(&work),
+            Work([0x3894_6224_e380, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn test_insufficient_proof_of_work() {
+        let header = Network::Mainnet.genesis_block_header();
+        let header = BlockHeader::new(
+            *header.version(),
+            *header.prev_block_hash(),
+            *header.merkle_root(),
+            *header.time(),
+            *header.bits(),
+            header.nonce() + 1,
+        );
+
+        assert!(matches!(
+            header.check_proof_of_work(&Network::Mainnet),
+            Err(SerdeBitcoinError::InsufficientProofOfWork(_))
+        ));
+    }
+}
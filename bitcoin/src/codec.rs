@@ -0,0 +1,283 @@
+use crate::message_type::MessageType;
+use crate::network::Network;
+use crate::{
+    Message, SerdeBitcoin, SerdeBitcoinError, MAGIC_BYTES_LENGTH, MAX_PROTOCOL_MESSAGE_LENGTH,
+};
+use bytes::BytesMut;
+use std::collections::HashMap;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Offset of the message type field within the message header
+const MESSAGE_TYPE_OFFSET: usize = 4;
+
+/// Offset of the payload length field within the message header
+const PAYLOAD_LENGTH_OFFSET: usize = 16;
+
+/// Maximum payload length accepted for each message type
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadLimits {
+    default: u32,
+    per_type: HashMap<MessageType, u32>,
+}
+
+impl PayloadLimits {
+    pub fn new(default: u32) -> Self {
+        Self {
+            default,
+            per_type: HashMap::new(),
+        }
+    }
+
+    /// Overrides the limit for a single message type
+    pub fn with_limit(mut self, ty: MessageType, limit: u32) -> Self {
+        self.per_type.insert(ty, limit);
+        self
+    }
+
+    pub fn limit(&self, ty: &MessageType) -> u32 {
+        self.per_type.get(ty).copied().unwrap_or(self.default)
+    }
+}
+
+impl Default for PayloadLimits {
+    fn default() -> Self {
+        Self::new(MAX_PROTOCOL_MESSAGE_LENGTH)
+    }
+}
+
+/// Frames `Message`s over a byte stream.
+///
+/// The header is decoded first and the frame is only emitted once the whole payload has arrived,
+/// any bytes left over belong to the next message and are kept in the buffer.
+/// Messages whose magic bytes belong to another network are rejected.
+#[derive(Debug, Clone)]
+pub struct MessageCodec {
+    magic_bytes: [u8; MAGIC_BYTES_LENGTH],
+    limits: PayloadLimits,
+}
+
+impl MessageCodec {
+    pub fn new(network: &Network, limits: PayloadLimits) -> Self {
+        Self {
+            magic_bytes: network.magic_bytes(),
+            limits,
+        }
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = SerdeBitcoinError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, SerdeBitcoinError> {
+        // Wait for the whole header
+        if src.len() < Message::BASE_SIZE {
+            src.reserve(Message::BASE_SIZE - src.len());
+            return Ok(None);
+        }
+
+        let mut magic_bytes = [0u8; MAGIC_BYTES_LENGTH];
+        magic_bytes.copy_from_slice(&src[..MAGIC_BYTES_LENGTH]);
+        if magic_bytes != self.magic_bytes {
+            return Err(SerdeBitcoinError::InvalidMagicBytes(
+                self.magic_bytes,
+                magic_bytes,
+            ));
+        }
+
+        let mut message_type_bytes = [0u8; MessageType::SIZE];
+        message_type_bytes.copy_from_slice(&src[MESSAGE_TYPE_OFFSET..PAYLOAD_LENGTH_OFFSET]);
+        let message_type = MessageType::deserialize(&mut message_type_bytes)?;
+
+        let mut payload_length_bytes = [0u8; 4];
+        payload_length_bytes
+            .copy_from_slice(&src[PAYLOAD_LENGTH_OFFSET..PAYLOAD_LENGTH_OFFSET + 4]);
+        let payload_length = u32::from_le_bytes(payload_length_bytes);
+
+        // Reject the message before reserving any space for the payload
+        let limit = self.limits.limit(&message_type);
+        if payload_length > limit {
+            return Err(SerdeBitcoinError::PayloadTooLarge(
+                message_type.to_string(),
+                payload_length,
+                limit,
+            ));
+        }
+        let payload_length =
+            usize::try_from(payload_length).map_err(SerdeBitcoinError::InvalidPayloadLength)?;
+
+        // Wait for the whole payload
+        let frame_length = Message::BASE_SIZE + payload_length;
+        if src.len() < frame_length {
+            src.reserve(frame_length - src.len());
+            return Ok(None);
+        }
+
+        let mut frame = src.split_to(frame_length);
+        Message::deserialize(&mut frame).map(Some)
+    }
+}
+
+impl Encoder<Message> for MessageCodec {
+    type Error = SerdeBitcoinError;
+
+    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), SerdeBitcoinError> {
+        dst.extend_from_slice(&item.serialize()?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message_type::MessageType;
+    use crate::network::Network;
+    use crate::verack::VerAck;
+    use crate::version::VersionBuilder;
+    use crate::Payload;
+    use std::net::SocketAddr;
+
+    fn version_message() -> Message {
+        let version = VersionBuilder::default()
+            .receiver_address("127.0.0.1:18333".parse::<SocketAddr>().unwrap())
+            .sender_address("127.0.0.1:18334".parse::<SocketAddr>().unwrap())
+            .build()
+            .unwrap();
+
+        Message::build(
+            Payload::Version(version),
+            MessageType::Version,
+            &Network::Testnet,
+        )
+    }
+
+    #[test]
+    fn test_split_message() {
+        let message = version_message();
+        let serialized_bytes = message.serialize().expect("serialize");
+        let mut codec = MessageCodec::new(&Network::Testnet, PayloadLimits::default());
+        let mut buffer = BytesMut::new();
+
+        // Only part of the header
+        buffer.extend_from_slice(&serialized_bytes[..10]);
+        assert!(codec.decode(&mut buffer).expect("decode").is_none());
+
+        // The header and part of the payload
+        buffer.extend_from_slice(&serialized_bytes[10..40]);
+        assert!(codec.decode(&mut buffer).expect("decode").is_none());
+
+        // The rest of the payload
+        buffer.extend_from_slice(&serialized_bytes[40..]);
+        let decoded = codec.decode(&mut buffer).expect("decode");
+        assert_eq!(decoded, Some(message));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_coalesced_messages() {
+        let version = version_message();
+        let verack = Message::build(
+            Payload::VerAck(VerAck),
+            MessageType::VerAck,
+            &Network::Testnet,
+        );
+        let mut codec = MessageCodec::new(&Network::Testnet, PayloadLimits::default());
+        let mut buffer = BytesMut::new();
+
+        // Both messages arrive in the same read
+        buffer.extend_from_slice(&version.serialize().expect("serialize"));
+        buffer.extend_from_slice(&verack.serialize().expect("serialize"));
+
+        assert_eq!(codec.decode(&mut buffer).expect("decode"), Some(version));
+        assert_eq!(codec.decode(&mut buffer).expect("decode"), Some(verack));
+        assert!(codec.decode(&mut buffer).expect("decode").is_none());
+    }
+
+    #[test]
+    fn test_encode() {
+        let message = version_message();
+        let expected = message.serialize().expect("serialize");
+        let mut codec = MessageCodec::new(&Network::Testnet, PayloadLimits::default());
+        let mut buffer = BytesMut::new();
+
+        codec.encode(message, &mut buffer).expect("encode");
+
+        assert_eq!(buffer.as_ref(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_payload_too_large() {
+        let serialized_bytes = version_message().serialize().expect("serialize");
+        let mut codec = MessageCodec::new(
+            &Network::Testnet,
+            PayloadLimits::default().with_limit(MessageType::Version, 10),
+        );
+        let mut buffer = BytesMut::new();
+
+        // Only the header is needed to reject it
+        buffer.extend_from_slice(&serialized_bytes[..Message::BASE_SIZE]);
+
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(SerdeBitcoinError::PayloadTooLarge(_, _, 10))
+        ));
+    }
+
+    #[test]
+    fn test_limit_above_default() {
+        let mut header = Message::build(
+            Payload::VerAck(VerAck),
+            MessageType::VerAck,
+            &Network::Testnet,
+        )
+        .serialize()
+        .expect("serialize");
+        header[PAYLOAD_LENGTH_OFFSET..PAYLOAD_LENGTH_OFFSET + 4]
+            .copy_from_slice(&(MAX_PROTOCOL_MESSAGE_LENGTH + 1).to_le_bytes());
+        let mut codec = MessageCodec::new(
+            &Network::Testnet,
+            PayloadLimits::default()
+                .with_limit(MessageType::VerAck, MAX_PROTOCOL_MESSAGE_LENGTH * 2),
+        );
+        let mut buffer = BytesMut::from(header.as_slice());
+
+        // Assert that the configured limit wins over the default one, the payload is awaited
+        assert!(codec.decode(&mut buffer).expect("decode").is_none());
+    }
+
+    #[test]
+    fn test_default_limit() {
+        let mut header = Message::build(
+            Payload::VerAck(VerAck),
+            MessageType::VerAck,
+            &Network::Testnet,
+        )
+        .serialize()
+        .expect("serialize");
+        header[PAYLOAD_LENGTH_OFFSET..PAYLOAD_LENGTH_OFFSET + 4]
+            .copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut codec = MessageCodec::new(&Network::Testnet, PayloadLimits::default());
+        let mut buffer = BytesMut::from(header.as_slice());
+
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(SerdeBitcoinError::PayloadTooLarge(
+                _,
+                u32::MAX,
+                MAX_PROTOCOL_MESSAGE_LENGTH
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_wrong_network() {
+        let serialized_bytes = version_message().serialize().expect("serialize");
+        let mut codec = MessageCodec::new(&Network::Mainnet, PayloadLimits::default());
+        let mut buffer = BytesMut::from(serialized_bytes.as_slice());
+
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(SerdeBitcoinError::InvalidMagicBytes(_, _))
+        ));
+    }
+}
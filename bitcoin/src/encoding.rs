@@ -0,0 +1,184 @@
+use crate::hash::Hash256;
+use crate::SerdeBitcoinError;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{ErrorKind, Read, Write};
+
+/// Building blocks shared by the payloads to read variable length fields
+pub trait ReadBitcoinExt: Read {
+    /// Reads a CompactSize (a.k.a. VarInt), rejecting non-minimal encodings
+    fn read_compact_size(&mut self) -> Result<u64, SerdeBitcoinError> {
+        let (value, min) = match self.read_u8()? {
+            0xff => (self.read_u64::<LittleEndian>()?, 0x1_0000_0000),
+            0xfe => (u64::from(self.read_u32::<LittleEndian>()?), 0x1_0000),
+            0xfd => (u64::from(self.read_u16::<LittleEndian>()?), 0xfd),
+            value => return Ok(u64::from(value)),
+        };
+
+        if value < min {
+            return Err(SerdeBitcoinError::NonCanonicalCompactSize(value));
+        }
+
+        Ok(value)
+    }
+
+    /// Reads a byte vector prefixed with its CompactSize length
+    fn read_var_bytes(&mut self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let length = self.read_compact_size()?;
+
+        // The length is untrusted, so only allocate as the bytes arrive
+        let mut result = Vec::new();
+        (&mut *self).take(length).read_to_end(&mut result)?;
+        if result.len() as u64 != length {
+            return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+        }
+
+        Ok(result)
+    }
+
+    /// Reads a string prefixed with its CompactSize length
+    fn read_var_str(&mut self) -> Result<String, SerdeBitcoinError> {
+        String::from_utf8(self.read_var_bytes()?).map_err(SerdeBitcoinError::InvalidVarStr)
+    }
+
+    /// Reads a 32 bytes hash
+    fn read_hash(&mut self) -> Result<Hash256, SerdeBitcoinError> {
+        let mut bytes = [0u8; 32];
+        self.read_exact(&mut bytes)?;
+        Ok(Hash256::new(bytes))
+    }
+}
+
+impl<R: Read + ?Sized> ReadBitcoinExt for R {}
+
+/// Building blocks shared by the payloads to write variable length fields
+pub trait WriteBitcoinExt: Write {
+    /// Writes a CompactSize (a.k.a. VarInt) using the minimal encoding
+    fn write_compact_size(&mut self, value: u64) -> Result<(), SerdeBitcoinError> {
+        match value {
+            0..=0xfc => self.write_u8(value as u8)?,
+            0xfd..=0xffff => {
+                self.write_u8(0xfd)?;
+                self.write_u16::<LittleEndian>(value as u16)?;
+            }
+            0x1_0000..=0xffff_ffff => {
+                self.write_u8(0xfe)?;
+                self.write_u32::<LittleEndian>(value as u32)?;
+            }
+            _ => {
+                self.write_u8(0xff)?;
+                self.write_u64::<LittleEndian>(value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes a byte slice prefixed with its CompactSize length
+    fn write_var_bytes(&mut self, value: &[u8]) -> Result<(), SerdeBitcoinError> {
+        self.write_compact_size(value.len() as u64)?;
+        self.write_all(value)?;
+        Ok(())
+    }
+
+    /// Writes a string prefixed with its CompactSize length
+    fn write_var_str(&mut self, value: &str) -> Result<(), SerdeBitcoinError> {
+        self.write_var_bytes(value.as_bytes())
+    }
+
+    /// Writes a 32 bytes hash
+    fn write_hash(&mut self, value: &Hash256) -> Result<(), SerdeBitcoinError> {
+        self.write_all(value.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl<W: Write + ?Sized> WriteBitcoinExt for W {}
+
+/// Size in bytes of the CompactSize encoding of `value`
+pub fn compact_size_len(value: u64) -> usize {
+    match value {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_compact_size() {
+        for value in [
+            0,
+            0xfc,
+            0xfd,
+            0xffff,
+            0x1_0000,
+            0xffff_ffff,
+            0x1_0000_0000,
+            u64::MAX,
+        ] {
+            // Serialize the CompactSize into a Vec<u8>
+            let mut serialized_bytes = Vec::new();
+            serialized_bytes
+                .write_compact_size(value)
+                .expect("serialize");
+
+            // Assert that the serialized bytes length is as expected
+            assert_eq!(serialized_bytes.len(), compact_size_len(value));
+
+            // Deserialize the bytes back to the value
+            let deserialized = Cursor::new(serialized_bytes)
+                .read_compact_size()
+                .expect("deserialize");
+
+            // Assert that the deserialized value matches the original value
+            assert_eq!(deserialized, value);
+        }
+    }
+
+    #[test]
+    fn test_non_canonical_compact_size() {
+        // 0x10 encoded with the 3 bytes prefix
+        let mut cursor = Cursor::new(vec![0xfd, 0x10, 0x00]);
+
+        assert!(matches!(
+            cursor.read_compact_size(),
+            Err(SerdeBitcoinError::NonCanonicalCompactSize(0x10))
+        ));
+    }
+
+    #[test]
+    fn test_var_str() {
+        // Long enough to need the 3 bytes CompactSize
+        let value = "a".repeat(300);
+
+        // Serialize the var_str into a Vec<u8>
+        let mut serialized_bytes = Vec::new();
+        serialized_bytes.write_var_str(&value).expect("serialize");
+
+        // Assert that the serialized bytes length is as expected
+        assert_eq!(serialized_bytes.len(), 3 + value.len());
+
+        // Deserialize the bytes back to the string
+        let deserialized = Cursor::new(serialized_bytes)
+            .read_var_str()
+            .expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, value);
+    }
+
+    #[test]
+    fn test_truncated_var_bytes() {
+        // Announces a huge length but carries a single byte
+        let mut cursor = Cursor::new(vec![
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+        ]);
+
+        assert!(cursor.read_var_bytes().is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use crate::message_type::MessageType;
+use crate::{Payload, SerdeBitcoin, SerdeBitcoinError};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use getset::Getters;
+use std::fmt;
+use std::io::Cursor;
+
+/// Signals that transactions are announced and requested by wtxid (BIP339), it must be sent
+/// between version and verack
+#[derive(Debug, PartialEq)]
+pub struct WtxIdRelay;
+
+impl SerdeBitcoin for WtxIdRelay {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        Ok(vec![])
+    }
+
+    fn deserialize(_data: &mut [u8]) -> Result<WtxIdRelay, SerdeBitcoinError> {
+        Ok(WtxIdRelay {})
+    }
+}
+
+/// Signals support for transaction reconciliation (BIP330), it must be sent between version and
+/// verack
+#[derive(Getters, Debug, Clone, Copy, PartialEq)]
+pub struct SendTxRcncl {
+    #[getset(get = "pub")]
+    version: u32,
+
+    #[getset(get = "pub")]
+    salt: u64,
+}
+
+impl SendTxRcncl {
+    pub fn new(version: u32, salt: u64) -> Self {
+        Self { version, salt }
+    }
+}
+
+impl SerdeBitcoin for SendTxRcncl {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::with_capacity(12);
+        result.write_u32::<LittleEndian>(self.version)?;
+        result.write_u64::<LittleEndian>(self.salt)?;
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<SendTxRcncl, SerdeBitcoinError> {
+        let mut cursor = Cursor::new(data);
+        Ok(SendTxRcncl {
+            version: cursor.read_u32::<LittleEndian>()?,
+            salt: cursor.read_u64::<LittleEndian>()?,
+        })
+    }
+}
+
+/// Asks to announce new blocks with headers instead of inv (BIP130)
+#[derive(Debug, PartialEq)]
+pub struct SendHeaders;
+
+impl SerdeBitcoin for SendHeaders {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        Ok(vec![])
+    }
+
+    fn deserialize(_data: &mut [u8]) -> Result<SendHeaders, SerdeBitcoinError> {
+        Ok(SendHeaders {})
+    }
+}
+
+/// Signals support for compact blocks (BIP152), `announce` asks for new blocks to be sent as
+/// compact blocks without announcing them first
+#[derive(Getters, Debug, Clone, Copy, PartialEq)]
+pub struct SendCmpct {
+    #[getset(get = "pub")]
+    announce: bool,
+
+    #[getset(get = "pub")]
+    version: u64,
+}
+
+impl SendCmpct {
+    pub fn new(announce: bool, version: u64) -> Self {
+        Self { announce, version }
+    }
+}
+
+impl SerdeBitcoin for SendCmpct {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::with_capacity(9);
+        result.write_u8(self.announce.into())?;
+        result.write_u64::<LittleEndian>(self.version)?;
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<SendCmpct, SerdeBitcoinError> {
+        let mut cursor = Cursor::new(data);
+        Ok(SendCmpct {
+            announce: cursor.read_u8()? != 0,
+            version: cursor.read_u64::<LittleEndian>()?,
+        })
+    }
+}
+
+/// Asks not to announce transactions below the fee rate, in satoshis per 1000 virtual bytes
+/// (BIP133)
+#[derive(Getters, Debug, Clone, Copy, PartialEq)]
+pub struct FeeFilter {
+    #[getset(get = "pub")]
+    fee_rate: u64,
+}
+
+impl FeeFilter {
+    pub fn new(fee_rate: u64) -> Self {
+        Self { fee_rate }
+    }
+}
+
+impl SerdeBitcoin for FeeFilter {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::with_capacity(8);
+        result.write_u64::<LittleEndian>(self.fee_rate)?;
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<FeeFilter, SerdeBitcoinError> {
+        let mut cursor = Cursor::new(data);
+        Ok(FeeFilter {
+            fee_rate: cursor.read_u64::<LittleEndian>()?,
+        })
+    }
+}
+
+/// Checks if the message type negotiates a feature that must be sent between version and verack
+pub fn is_negotiated_before_verack(ty: &MessageType) -> bool {
+    matches!(
+        ty,
+        MessageType::WtxIdRelay | MessageType::SendAddrV2 | MessageType::SendTxRcncl
+    )
+}
+
+/// Features negotiated by a peer
+#[derive(Getters, Debug, Clone, Default, PartialEq)]
+pub struct Features {
+    #[getset(get = "pub")]
+    wtxid_relay: bool,
+
+    /// The peer asked to receive addrv2 messages instead of addr
+    #[getset(get = "pub")]
+    addr_v2: bool,
+
+    #[getset(get = "pub")]
+    tx_reconciliation: Option<SendTxRcncl>,
+
+    #[getset(get = "pub")]
+    send_headers: bool,
+
+    /// Highest compact blocks version announced
+    #[getset(get = "pub")]
+    compact_blocks: Option<SendCmpct>,
+
+    #[getset(get = "pub")]
+    fee_filter: Option<u64>,
+}
+
+impl Features {
+    /// Records the feature negotiated by the payload, returns false for any other payload
+    pub fn record(&mut self, payload: &Payload) -> bool {
+        match payload {
+            Payload::WtxIdRelay(_) => self.wtxid_relay = true,
+            Payload::SendAddrV2(_) => self.addr_v2 = true,
+            Payload::SendTxRcncl(send_tx_rcncl) => self.tx_reconciliation = Some(*send_tx_rcncl),
+            Payload::SendHeaders(_) => self.send_headers = true,
+            Payload::SendCmpct(send_cmpct) => {
+                let highest = self
+                    .compact_blocks
+                    .is_none_or(|current| send_cmpct.version >= current.version);
+                if highest {
+                    self.compact_blocks = Some(*send_cmpct);
+                }
+            }
+            Payload::FeeFilter(fee_filter) => self.fee_filter = Some(fee_filter.fee_rate),
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut features = Vec::new();
+        if self.wtxid_relay {
+            features.push("wtxidrelay".to_string());
+        }
+        if self.addr_v2 {
+            features.push("addrv2".to_string());
+        }
+        if let Some(send_tx_rcncl) = &self.tx_reconciliation {
+            features.push(format!("txrcncl v{}", send_tx_rcncl.version));
+        }
+        if self.send_headers {
+            features.push("sendheaders".to_string());
+        }
+        if let Some(send_cmpct) = &self.compact_blocks {
+            features.push(format!("cmpct v{}", send_cmpct.version));
+        }
+        if let Some(fee_rate) = self.fee_filter {
+            features.push(format!("feefilter {fee_rate} sat/kvB"));
+        }
+
+        if features.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", features.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::addr::SendAddrV2;
+
+    #[test]
+    fn test_send_cmpct() {
+        // Create a SendCmpct
+        let send_cmpct = SendCmpct::new(true, 2);
+
+        // Serialize the SendCmpct into a Vec<u8>
+        let mut serialized_bytes = send_cmpct.serialize().expect("serialize");
+
+        // Assert that the serialized bytes match the expected ones
+        assert_eq!(serialized_bytes, vec![1, 2, 0, 0, 0, 0, 0, 0, 0]);
+
+        // Deserialize the bytes back to SendCmpct
+        let deserialized =
+            SendCmpct::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, send_cmpct);
+    }
+
+    #[test]
+    fn test_fee_filter() {
+        // Create a FeeFilter
+        let fee_filter = FeeFilter::new(1000);
+
+        // Serialize the FeeFilter into a Vec<u8>
+        let mut serialized_bytes = fee_filter.serialize().expect("serialize");
+
+        // Assert that the serialized bytes length is as expected
+        assert_eq!(serialized_bytes.len(), 8);
+
+        // Deserialize the bytes back to FeeFilter
+        let deserialized =
+            FeeFilter::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, fee_filter);
+    }
+
+    #[test]
+    fn test_send_tx_rcncl() {
+        // Create a SendTxRcncl
+        let send_tx_rcncl = SendTxRcncl::new(1, 0x0102_0304_0506_0708);
+
+        // Serialize the SendTxRcncl into a Vec<u8>
+        let mut serialized_bytes = send_tx_rcncl.serialize().expect("serialize");
+
+        // Assert that the serialized bytes length is as expected
+        assert_eq!(serialized_bytes.len(), 12);
+
+        // Deserialize the bytes back to SendTxRcncl
+        let deserialized =
+            SendTxRcncl::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, send_tx_rcncl);
+    }
+
+    #[test]
+    fn test_features() {
+        let mut features = Features::default();
+        assert_eq!(features.to_string(), "none");
+
+        for payload in [
+            Payload::WtxIdRelay(WtxIdRelay),
+            Payload::SendAddrV2(SendAddrV2),
+            Payload::SendHeaders(SendHeaders),
+            Payload::SendCmpct(SendCmpct::new(false, 2)),
+            Payload::SendCmpct(SendCmpct::new(false, 1)),
+            Payload::FeeFilter(FeeFilter::new(1000)),
+        ] {
+            assert!(features.record(&payload));
+        }
+
+        // Assert that only the highest compact blocks version is kept
+        assert_eq!(*features.compact_blocks(), Some(SendCmpct::new(false, 2)));
+        assert_eq!(
+            features.to_string(),
+            "wtxidrelay, addrv2, sendheaders, cmpct v2, feefilter 1000 sat/kvB"
+        );
+
+        // Other payloads are not features
+        assert!(!features.record(&Payload::GetAddr(crate::addr::GetAddr)));
+    }
+}
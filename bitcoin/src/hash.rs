@@ -0,0 +1,76 @@
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+/// SHA256 applied twice, as used for checksums and hashes across the protocol
+pub fn double_sha256(data: &[u8]) -> [u8; 32] {
+    let first_hash = Sha256::digest(data);
+    Sha256::digest(first_hash).into()
+}
+
+/// 32 bytes hash identifying blocks and transactions.
+///
+/// The bytes are kept in the order they are sent on the wire, but like Bitcoin Core they are
+/// displayed and parsed reversed.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Hash256([u8; 32]);
+
+impl Hash256 {
+    pub const ZERO: Hash256 = Hash256([0; 32]);
+
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Double SHA256 of `data`
+    pub fn hash(data: &[u8]) -> Self {
+        Self(double_sha256(data))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut reversed = self.0;
+        reversed.reverse();
+        write!(f, "{}", hex::encode(reversed))
+    }
+}
+
+impl fmt::Debug for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hash256({self})")
+    }
+}
+
+impl FromStr for Hash256 {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = [0u8; 32];
+        hex::decode_to_slice(s, &mut bytes)?;
+        bytes.reverse();
+        Ok(Self(bytes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        // Mainnet genesis block hash
+        let expected = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+        let hash = expected.parse::<Hash256>().expect("parse");
+
+        // Assert that the bytes are stored in wire order
+        assert_eq!(hash.as_bytes()[0], 0x6f);
+
+        // Assert that the displayed value matches the original value
+        assert_eq!(hash.to_string(), expected);
+    }
+}
@@ -0,0 +1,264 @@
+use crate::block_header::BlockHeader;
+use crate::encoding::{ReadBitcoinExt, WriteBitcoinExt};
+use crate::hash::Hash256;
+use crate::{SerdeBitcoin, SerdeBitcoinError};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use getset::Getters;
+use std::io::Cursor;
+
+/// Maximum number of headers in a single headers message
+pub const MAX_HEADERS_RESULTS: u64 = 2000;
+
+/// Maximum number of hashes in a block locator
+const MAX_LOCATOR_SZ: u64 = 101;
+
+/// Number of most recent blocks added one by one to a block locator
+const LOCATOR_DENSE_LENGTH: usize = 10;
+
+/// Builds a block locator from a chain of hashes ordered from the genesis block to the tip.
+///
+/// The most recent hashes are added one by one and then exponentially further apart, the genesis
+/// hash is always the last one.
+pub fn block_locator(chain: &[Hash256]) -> Vec<Hash256> {
+    let mut locator = Vec::new();
+    let Some(mut index) = chain.len().checked_sub(1) else {
+        return locator;
+    };
+
+    let mut step = 1;
+    loop {
+        locator.push(chain[index]);
+        if index == 0 {
+            return locator;
+        }
+        if locator.len() >= LOCATOR_DENSE_LENGTH {
+            step *= 2;
+        }
+        index = index.saturating_sub(step);
+    }
+}
+
+#[derive(Getters, Debug, Default, PartialEq)]
+pub struct Headers {
+    #[getset(get = "pub")]
+    headers: Vec<BlockHeader>,
+}
+
+impl Headers {
+    pub fn new(headers: Vec<BlockHeader>) -> Self {
+        Self { headers }
+    }
+}
+
+impl SerdeBitcoin for Headers {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::with_capacity(9 + self.headers.len() * (BlockHeader::SIZE + 1));
+        result.write_compact_size(self.headers.len() as u64)?;
+        for header in &self.headers {
+            header.write(&mut result)?;
+            // Number of transactions, always empty
+            result.write_compact_size(0)?;
+        }
+
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<Headers, SerdeBitcoinError> {
+        let mut cursor = Cursor::new(data);
+        let count = cursor.read_compact_size()?;
+        if count > MAX_HEADERS_RESULTS {
+            return Err(SerdeBitcoinError::TooManyHeaders(count));
+        }
+
+        let headers = (0..count)
+            .map(|_| {
+                let header = BlockHeader::read(&mut cursor)?;
+                // Number of transactions, ignored
+                cursor.read_compact_size()?;
+                Ok(header)
+            })
+            .collect::<Result<_, SerdeBitcoinError>>()?;
+
+        Ok(Headers { headers })
+    }
+}
+
+/// Content shared by getheaders and getblocks
+#[derive(Debug, PartialEq)]
+struct Locator {
+    version: u32,
+    locator_hashes: Vec<Hash256>,
+    hash_stop: Hash256,
+}
+
+impl SerdeBitcoin for Locator {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::with_capacity(4 + 9 + (self.locator_hashes.len() + 1) * 32);
+        result.write_u32::<LittleEndian>(self.version)?;
+        result.write_compact_size(self.locator_hashes.len() as u64)?;
+        for hash in &self.locator_hashes {
+            result.write_hash(hash)?;
+        }
+        result.write_hash(&self.hash_stop)?;
+
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<Locator, SerdeBitcoinError> {
+        let mut cursor = Cursor::new(data);
+        let version = cursor.read_u32::<LittleEndian>()?;
+        let count = cursor.read_compact_size()?;
+        if count > MAX_LOCATOR_SZ {
+            return Err(SerdeBitcoinError::TooManyLocatorHashes(count));
+        }
+        let locator_hashes = (0..count)
+            .map(|_| cursor.read_hash())
+            .collect::<Result<_, _>>()?;
+        let hash_stop = cursor.read_hash()?;
+
+        Ok(Locator {
+            version,
+            locator_hashes,
+            hash_stop,
+        })
+    }
+}
+
+/// Requests the headers following the first locator hash found in the peer's best chain
+#[derive(Debug, PartialEq)]
+pub struct GetHeaders(Locator);
+
+impl GetHeaders {
+    /// A zero `hash_stop` requests as many headers as possible
+    pub fn new(version: u32, locator_hashes: Vec<Hash256>, hash_stop: Hash256) -> Self {
+        Self(Locator {
+            version,
+            locator_hashes,
+            hash_stop,
+        })
+    }
+
+    pub fn version(&self) -> u32 {
+        self.0.version
+    }
+
+    pub fn locator_hashes(&self) -> &Vec<Hash256> {
+        &self.0.locator_hashes
+    }
+
+    pub fn hash_stop(&self) -> &Hash256 {
+        &self.0.hash_stop
+    }
+}
+
+impl SerdeBitcoin for GetHeaders {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        self.0.serialize()
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<GetHeaders, SerdeBitcoinError> {
+        Ok(GetHeaders(Locator::deserialize(data)?))
+    }
+}
+
+/// Requests an inv of the blocks following the first locator hash found in the peer's best chain
+#[derive(Debug, PartialEq)]
+pub struct GetBlocks(Locator);
+
+impl GetBlocks {
+    /// A zero `hash_stop` requests as many blocks as possible
+    pub fn new(version: u32, locator_hashes: Vec<Hash256>, hash_stop: Hash256) -> Self {
+        Self(Locator {
+            version,
+            locator_hashes,
+            hash_stop,
+        })
+    }
+
+    pub fn version(&self) -> u32 {
+        self.0.version
+    }
+
+    pub fn locator_hashes(&self) -> &Vec<Hash256> {
+        &self.0.locator_hashes
+    }
+
+    pub fn hash_stop(&self) -> &Hash256 {
+        &self.0.hash_stop
+    }
+}
+
+impl SerdeBitcoin for GetBlocks {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        self.0.serialize()
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<GetBlocks, SerdeBitcoinError> {
+        Ok(GetBlocks(Locator::deserialize(data)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::network::Network;
+
+    #[test]
+    fn test_headers() {
+        // Create a Headers
+        let headers = Headers::new(vec![
+            Network::Mainnet.genesis_block_header(),
+            Network::Regtest.genesis_block_header(),
+        ]);
+
+        // Serialize the Headers into a Vec<u8>
+        let mut serialized_bytes = headers.serialize().expect("serialize");
+
+        // Assert that the serialized bytes length is as expected
+        assert_eq!(serialized_bytes.len(), 1 + 2 * (BlockHeader::SIZE + 1));
+
+        // Deserialize the bytes back to Headers
+        let deserialized: Headers =
+            Headers::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, headers);
+    }
+
+    #[test]
+    fn test_getheaders() {
+        // Create a GetHeaders
+        let get_headers = GetHeaders::new(
+            70016,
+            vec![Network::Mainnet.genesis_block_header().block_hash()],
+            Hash256::ZERO,
+        );
+
+        // Serialize the GetHeaders into a Vec<u8>
+        let mut serialized_bytes = get_headers.serialize().expect("serialize");
+
+        // Assert that the serialized bytes length is as expected
+        assert_eq!(serialized_bytes.len(), 4 + 1 + 2 * 32);
+
+        // Deserialize the bytes back to GetHeaders
+        let deserialized: GetHeaders =
+            GetHeaders::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, get_headers);
+    }
+
+    #[test]
+    fn test_block_locator() {
+        let chain: Vec<Hash256> = (0..100u8).map(|i| Hash256::new([i; 32])).collect();
+        let locator = block_locator(&chain);
+
+        let heights: Vec<u8> = locator.iter().map(|hash| hash.as_bytes()[0]).collect();
+        assert_eq!(
+            heights,
+            vec![99, 98, 97, 96, 95, 94, 93, 92, 91, 90, 88, 84, 76, 60, 28, 0]
+        );
+
+        assert!(block_locator(&[]).is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+use crate::encoding::{ReadBitcoinExt, WriteBitcoinExt};
+use crate::hash::Hash256;
+use crate::{SerdeBitcoin, SerdeBitcoinError};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use getset::Getters;
+use std::fmt;
+use std::io::{Cursor, Read, Write};
+
+/// Maximum number of entries in a single inv, getdata or notfound message
+const MAX_INV_SZ: u64 = 50_000;
+
+/// Size of a serialized inventory vector
+const INV_VECTOR_SIZE: usize = 36;
+
+/// Flag set on the inventory types requesting witness data (BIP144)
+const MSG_WITNESS_FLAG: u32 = 1 << 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InvType {
+    Error,
+    Tx,
+    Block,
+    FilteredBlock,
+    CompactBlock,
+    /// Transaction identified by its wtxid (BIP339)
+    Wtx,
+    WitnessTx,
+    WitnessBlock,
+    FilteredWitnessBlock,
+    /// Type not known, kept as is
+    Unknown(u32),
+}
+
+impl From<u32> for InvType {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => InvType::Error,
+            1 => InvType::Tx,
+            2 => InvType::Block,
+            3 => InvType::FilteredBlock,
+            4 => InvType::CompactBlock,
+            5 => InvType::Wtx,
+            x if x == MSG_WITNESS_FLAG | 1 => InvType::WitnessTx,
+            x if x == MSG_WITNESS_FLAG | 2 => InvType::WitnessBlock,
+            x if x == MSG_WITNESS_FLAG | 3 => InvType::FilteredWitnessBlock,
+            x => InvType::Unknown(x),
+        }
+    }
+}
+
+impl From<InvType> for u32 {
+    fn from(value: InvType) -> Self {
+        match value {
+            InvType::Error => 0,
+            InvType::Tx => 1,
+            InvType::Block => 2,
+            InvType::FilteredBlock => 3,
+            InvType::CompactBlock => 4,
+            InvType::Wtx => 5,
+            InvType::WitnessTx => MSG_WITNESS_FLAG | 1,
+            InvType::WitnessBlock => MSG_WITNESS_FLAG | 2,
+            InvType::FilteredWitnessBlock => MSG_WITNESS_FLAG | 3,
+            InvType::Unknown(x) => x,
+        }
+    }
+}
+
+impl fmt::Display for InvType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvType::Error => write!(f, "error"),
+            InvType::Tx => write!(f, "tx"),
+            InvType::Block => write!(f, "block"),
+            InvType::FilteredBlock => write!(f, "filtered_block"),
+            InvType::CompactBlock => write!(f, "compact_block"),
+            InvType::Wtx => write!(f, "wtx"),
+            InvType::WitnessTx => write!(f, "witness_tx"),
+            InvType::WitnessBlock => write!(f, "witness_block"),
+            InvType::FilteredWitnessBlock => write!(f, "filtered_witness_block"),
+            InvType::Unknown(x) => write!(f, "unknown({x})"),
+        }
+    }
+}
+
+#[derive(Getters, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InvVector {
+    #[getset(get = "pub")]
+    ty: InvType,
+
+    #[getset(get = "pub")]
+    hash: Hash256,
+}
+
+impl InvVector {
+    pub fn new(ty: InvType, hash: Hash256) -> Self {
+        Self { ty, hash }
+    }
+
+    fn read<R: Read + ?Sized>(reader: &mut R) -> Result<Self, SerdeBitcoinError> {
+        let ty = reader.read_u32::<LittleEndian>()?.into();
+        let hash = reader.read_hash()?;
+        Ok(Self { ty, hash })
+    }
+
+    fn write<W: Write + ?Sized>(&self, writer: &mut W) -> Result<(), SerdeBitcoinError> {
+        writer.write_u32::<LittleEndian>(self.ty.into())?;
+        writer.write_hash(&self.hash)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for InvVector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.ty, self.hash)
+    }
+}
+
+fn serialize_inventory(inventory: &[InvVector]) -> Result<Vec<u8>, SerdeBitcoinError> {
+    let mut result = Vec::with_capacity(9 + inventory.len() * INV_VECTOR_SIZE);
+    result.write_compact_size(inventory.len() as u64)?;
+    for entry in inventory {
+        entry.write(&mut result)?;
+    }
+
+    Ok(result)
+}
+
+fn deserialize_inventory(data: &mut [u8]) -> Result<Vec<InvVector>, SerdeBitcoinError> {
+    let mut cursor = Cursor::new(data);
+    let count = cursor.read_compact_size()?;
+    if count > MAX_INV_SZ {
+        return Err(SerdeBitcoinError::TooManyInventoryEntries(count));
+    }
+
+    (0..count).map(|_| InvVector::read(&mut cursor)).collect()
+}
+
+/// Announces transactions or blocks
+#[derive(Getters, Debug, Default, PartialEq)]
+pub struct Inv {
+    #[getset(get = "pub")]
+    inventory: Vec<InvVector>,
+}
+
+impl Inv {
+    pub fn new(inventory: Vec<InvVector>) -> Self {
+        Self { inventory }
+    }
+}
+
+impl SerdeBitcoin for Inv {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        serialize_inventory(&self.inventory)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<Inv, SerdeBitcoinError> {
+        Ok(Inv {
+            inventory: deserialize_inventory(data)?,
+        })
+    }
+}
+
+/// Requests the content of announced transactions or blocks
+#[derive(Getters, Debug, Default, PartialEq)]
+pub struct GetData {
+    #[getset(get = "pub")]
+    inventory: Vec<InvVector>,
+}
+
+impl GetData {
+    pub fn new(inventory: Vec<InvVector>) -> Self {
+        Self { inventory }
+    }
+}
+
+impl SerdeBitcoin for GetData {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        serialize_inventory(&self.inventory)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<GetData, SerdeBitcoinError> {
+        Ok(GetData {
+            inventory: deserialize_inventory(data)?,
+        })
+    }
+}
+
+/// Answers a getdata with the entries the peer does not have
+#[derive(Getters, Debug, Default, PartialEq)]
+pub struct NotFound {
+    #[getset(get = "pub")]
+    inventory: Vec<InvVector>,
+}
+
+impl NotFound {
+    pub fn new(inventory: Vec<InvVector>) -> Self {
+        Self { inventory }
+    }
+}
+
+impl SerdeBitcoin for NotFound {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        serialize_inventory(&self.inventory)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<NotFound, SerdeBitcoinError> {
+        Ok(NotFound {
+            inventory: deserialize_inventory(data)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inv() {
+        // Create an Inv with every inventory type
+        let inv = Inv::new(
+            [
+                InvType::Error,
+                InvType::Tx,
+                InvType::Block,
+                InvType::FilteredBlock,
+                InvType::CompactBlock,
+                InvType::Wtx,
+                InvType::WitnessTx,
+                InvType::WitnessBlock,
+                InvType::FilteredWitnessBlock,
+                InvType::Unknown(42),
+            ]
+            .into_iter()
+            .map(|ty| InvVector::new(ty, Hash256::new([7; 32])))
+            .collect(),
+        );
+
+        // Serialize the Inv into a Vec<u8>
+        let mut serialized_bytes = inv.serialize().expect("serialize");
+
+        // Assert that the serialized bytes length is as expected
+        assert_eq!(serialized_bytes.len(), 1 + 10 * INV_VECTOR_SIZE);
+
+        // Deserialize the bytes back to Inv
+        let deserialized: Inv =
+            Inv::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, inv);
+    }
+
+    #[test]
+    fn test_witness_inv_type() {
+        // MSG_WITNESS_BLOCK as sent on the wire
+        assert_eq!(InvType::from(0x4000_0002), InvType::WitnessBlock);
+        assert_eq!(u32::from(InvType::WitnessTx), 0x4000_0001);
+    }
+
+    #[test]
+    fn test_getdata() {
+        // Create a GetData
+        let get_data = GetData::new(vec![InvVector::new(InvType::Tx, Hash256::new([1; 32]))]);
+
+        // Serialize the GetData into a Vec<u8>
+        let mut serialized_bytes = get_data.serialize().expect("serialize");
+
+        // Deserialize the bytes back to GetData
+        let deserialized: GetData =
+            GetData::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, get_data);
+    }
+
+    #[test]
+    fn test_too_many_entries() {
+        let mut serialized_bytes = Vec::new();
+        serialized_bytes
+            .write_compact_size(MAX_INV_SZ + 1)
+            .expect("serialize");
+
+        assert!(matches!(
+            NotFound::deserialize(serialized_bytes.as_mut_slice()),
+            Err(SerdeBitcoinError::TooManyInventoryEntries(_))
+        ));
+    }
+}
@@ -1,15 +1,40 @@
+use crate::addr::{Addr, AddrV2, GetAddr, SendAddrV2};
+use crate::block::Block;
+use crate::feature::{FeeFilter, SendCmpct, SendHeaders, SendTxRcncl, WtxIdRelay};
+use crate::hash::{double_sha256, Hash256};
+use crate::headers::{GetBlocks, GetHeaders, Headers};
+use crate::inventory::{GetData, Inv, NotFound};
 use crate::message_type::MessageType;
+use crate::network::Network;
+use crate::ping::{Ping, Pong};
+use crate::reject::Reject;
+use crate::transaction::Transaction;
 use crate::verack::VerAck;
 use crate::version::Version;
 use byteorder::{LittleEndian, ReadBytesExt};
 use getset::Getters;
-use sha2::{Digest, Sha256};
 use std::io::{Cursor, Read};
 use std::num::TryFromIntError;
 use std::string::FromUtf8Error;
 use thiserror::Error;
 
+pub mod addr;
+pub mod block;
+pub mod block_header;
+pub mod codec;
+pub mod encoding;
+pub mod feature;
+#[cfg(test)]
+mod fixtures;
+pub mod hash;
+pub mod headers;
+pub mod inventory;
 pub mod message_type;
+pub mod network;
+pub mod ping;
+pub mod reject;
+pub mod services;
+pub mod transaction;
 pub mod verack;
 pub mod version;
 
@@ -28,28 +53,63 @@ pub enum SerdeBitcoinError {
     Infallible,
     #[error("Unknown/unsupported message type: {0}")]
     UnknownType(String),
+    #[error("Invalid message type {0:02x?}")]
+    InvalidMessageType(Vec<u8>),
     #[error("Io Error")]
     IoError(#[from] std::io::Error),
-    #[error("Invalid user agent length")]
-    InvalidUserAgentLength(#[source] TryFromIntError),
     #[error("Invalid payload length")]
     InvalidPayloadLength(#[source] TryFromIntError),
-    #[error("Failed to parse user agent")]
-    FailedToParseUserAgent(#[source] FromUtf8Error),
+    #[error("Failed to parse var_str")]
+    InvalidVarStr(#[source] FromUtf8Error),
+    #[error("Non-canonical CompactSize encoding for {0}")]
+    NonCanonicalCompactSize(u64),
     #[error("Failed to map to IPv4")]
     FailedToMapToIpv4,
     #[error("Invalid checksum")]
     InvalidChecksum,
+    #[error("Payload too large for {0}: {1} bytes, maximum is {2}")]
+    PayloadTooLarge(String, u32, u32),
+    #[error("Invalid magic bytes: expected {0:02x?}, received {1:02x?}")]
+    InvalidMagicBytes([u8; MAGIC_BYTES_LENGTH], [u8; MAGIC_BYTES_LENGTH]),
+    #[error("Too many addresses: {0}")]
+    TooManyAddresses(u64),
+    #[error("Invalid address length {1} for network id {0}")]
+    InvalidAddressLength(u8, usize),
+    #[error("Too many inventory entries: {0}")]
+    TooManyInventoryEntries(u64),
+    #[error("Too many headers: {0}")]
+    TooManyHeaders(u64),
+    #[error("Too many locator hashes: {0}")]
+    TooManyLocatorHashes(u64),
+    #[error("Invalid target {0:#010x}")]
+    InvalidTarget(u32),
+    #[error("Insufficient proof of work for block {0}")]
+    InsufficientProofOfWork(Hash256),
+    #[error("Invalid segwit flag {0:#04x}")]
+    InvalidSegwitFlag(u8),
+    #[error("Segwit serialization without witness data")]
+    SuperfluousWitness,
+    #[error("Block without transactions")]
+    EmptyBlock,
+    #[error("Invalid merkle root: header has {0}, computed {1}")]
+    InvalidMerkleRoot(Hash256, Hash256),
+    #[error("Merkle tree with duplicated transactions")]
+    MutatedMerkleTree,
+    #[error("Witness commitment does not match the witness data")]
+    InvalidWitnessCommitment,
+    #[error("Invalid witness reserved value in the coinbase")]
+    InvalidWitnessReservedValue,
+    #[error("Transaction {0} has witness data but the block has no witness commitment")]
+    UnexpectedWitness(Hash256),
+    #[error("Block weight {0} above the maximum")]
+    BlockTooHeavy(usize),
 }
 
-/// Magic bytes for mainnet
-const MAGIC_BYTES_MAINNET: [u8; 4] = [0xf9, 0xbe, 0xb4, 0xd9];
-
-/// Magic bytes for testnet
-const MAGIC_BYTES_TESTNET: [u8; 4] = [0x0b, 0x11, 0x09, 0x07];
+/// Maximum payload length accepted by default, same as Bitcoin Core's MAX_PROTOCOL_MESSAGE_LENGTH
+pub const MAX_PROTOCOL_MESSAGE_LENGTH: u32 = 4_000_000;
 
 /// Magic bytes Size
-const MAGIC_BYTES_LENGTH: usize = 4;
+pub const MAGIC_BYTES_LENGTH: usize = 4;
 
 /// Checksum Size
 const CHECKSUM_LENGTH: usize = 4;
@@ -58,6 +118,28 @@ const CHECKSUM_LENGTH: usize = 4;
 pub enum Payload {
     Version(Version),
     VerAck(VerAck),
+    Ping(Ping),
+    Pong(Pong),
+    Addr(Addr),
+    AddrV2(AddrV2),
+    GetAddr(GetAddr),
+    SendAddrV2(SendAddrV2),
+    WtxIdRelay(WtxIdRelay),
+    SendTxRcncl(SendTxRcncl),
+    SendHeaders(SendHeaders),
+    SendCmpct(SendCmpct),
+    FeeFilter(FeeFilter),
+    Inv(Inv),
+    GetData(GetData),
+    NotFound(NotFound),
+    Headers(Headers),
+    GetHeaders(GetHeaders),
+    GetBlocks(GetBlocks),
+    Tx(Transaction),
+    Block(Block),
+    Reject(Reject),
+    /// Payload of the message types that are not modeled, kept as received
+    Raw(Vec<u8>),
 }
 
 impl Payload {
@@ -65,6 +147,27 @@ impl Payload {
         match self {
             Payload::Version(version) => version.serialize(),
             Payload::VerAck(verack) => verack.serialize(),
+            Payload::Ping(ping) => ping.serialize(),
+            Payload::Pong(pong) => pong.serialize(),
+            Payload::Addr(addr) => addr.serialize(),
+            Payload::AddrV2(addr) => addr.serialize(),
+            Payload::GetAddr(get_addr) => get_addr.serialize(),
+            Payload::SendAddrV2(send_addr_v2) => send_addr_v2.serialize(),
+            Payload::WtxIdRelay(wtxid_relay) => wtxid_relay.serialize(),
+            Payload::SendTxRcncl(send_tx_rcncl) => send_tx_rcncl.serialize(),
+            Payload::SendHeaders(send_headers) => send_headers.serialize(),
+            Payload::SendCmpct(send_cmpct) => send_cmpct.serialize(),
+            Payload::FeeFilter(fee_filter) => fee_filter.serialize(),
+            Payload::Inv(inv) => inv.serialize(),
+            Payload::GetData(get_data) => get_data.serialize(),
+            Payload::NotFound(not_found) => not_found.serialize(),
+            Payload::Headers(headers) => headers.serialize(),
+            Payload::GetHeaders(get_headers) => get_headers.serialize(),
+            Payload::GetBlocks(get_blocks) => get_blocks.serialize(),
+            Payload::Tx(tx) => tx.serialize(),
+            Payload::Block(block) => block.serialize(),
+            Payload::Reject(reject) => reject.serialize(),
+            Payload::Raw(bytes) => Ok(bytes.clone()),
         }
     }
 }
@@ -82,36 +185,20 @@ pub struct Message {
 }
 
 impl Message {
-    const BASE_SIZE: usize = 24;
-
-    pub fn build(payload: Payload, ty: MessageType, testet: bool) -> Self {
-        let magic_bytes = if testet {
-            MAGIC_BYTES_TESTNET
-        } else {
-            MAGIC_BYTES_MAINNET
-        };
+    /// Header size: magic bytes, message type, payload length and checksum
+    pub const BASE_SIZE: usize = 24;
 
+    pub fn build(payload: Payload, ty: MessageType, network: &Network) -> Self {
         Self {
-            magic_bytes,
+            magic_bytes: network.magic_bytes(),
             ty,
             payload,
         }
     }
 
     fn build_checksum(payload: &[u8]) -> [u8; CHECKSUM_LENGTH] {
-        let mut hasher = Sha256::new();
-        hasher.update(payload);
-        let first_hash = hasher.finalize();
-
-        hasher = Sha256::new();
-        hasher.update(&first_hash);
-        let second_hash = hasher.finalize();
-
-        // @TODO: Remove the panic from here, it should never panic but it is better to propagate the error and handle it properly
-        let checksum: [u8; CHECKSUM_LENGTH] = second_hash[..CHECKSUM_LENGTH]
-            .try_into()
-            .expect("Wrong length for checksum");
-
+        let mut checksum = [0u8; CHECKSUM_LENGTH];
+        checksum.copy_from_slice(&double_sha256(payload)[..CHECKSUM_LENGTH]);
         checksum
     }
 }
@@ -167,12 +254,17 @@ impl SerdeBitcoin for Message {
         let mut checksum = [0u8; 4];
         cursor.read_exact(&mut checksum)?;
 
+        // The limits per message type are enforced by the codec, the length is only checked against
+        // the data so nothing is allocated for a payload that is not there
+        let payload_length =
+            usize::try_from(payload_length).map_err(SerdeBitcoinError::InvalidPayloadLength)?;
+        let remaining = cursor.get_ref().len() - cursor.position() as usize;
+        if payload_length > remaining {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+
         // Read Payload
-        let mut payload_bytes = vec![
-            0u8;
-            usize::try_from(payload_length)
-                .map_err(SerdeBitcoinError::InvalidPayloadLength)?
-        ];
+        let mut payload_bytes = vec![0u8; payload_length];
         cursor.read_exact(&mut payload_bytes)?;
 
         // Validate Payload
@@ -185,7 +277,43 @@ impl SerdeBitcoin for Message {
         let payload = match message_type {
             MessageType::Version => Payload::Version(Version::deserialize(&mut payload_bytes)?),
             MessageType::VerAck => Payload::VerAck(VerAck::deserialize(&mut payload_bytes)?),
-            ty => return Err(SerdeBitcoinError::UnknownType(ty.to_string())),
+            MessageType::Ping => Payload::Ping(Ping::deserialize(&mut payload_bytes)?),
+            MessageType::Pong => Payload::Pong(Pong::deserialize(&mut payload_bytes)?),
+            MessageType::Addr => Payload::Addr(Addr::deserialize(&mut payload_bytes)?),
+            MessageType::AddrV2 => Payload::AddrV2(AddrV2::deserialize(&mut payload_bytes)?),
+            MessageType::GetAddr => Payload::GetAddr(GetAddr::deserialize(&mut payload_bytes)?),
+            MessageType::SendAddrV2 => {
+                Payload::SendAddrV2(SendAddrV2::deserialize(&mut payload_bytes)?)
+            }
+            MessageType::WtxIdRelay => {
+                Payload::WtxIdRelay(WtxIdRelay::deserialize(&mut payload_bytes)?)
+            }
+            MessageType::SendTxRcncl => {
+                Payload::SendTxRcncl(SendTxRcncl::deserialize(&mut payload_bytes)?)
+            }
+            MessageType::SendHeaders => {
+                Payload::SendHeaders(SendHeaders::deserialize(&mut payload_bytes)?)
+            }
+            MessageType::SendCmpct => {
+                Payload::SendCmpct(SendCmpct::deserialize(&mut payload_bytes)?)
+            }
+            MessageType::FeeFilter => {
+                Payload::FeeFilter(FeeFilter::deserialize(&mut payload_bytes)?)
+            }
+            MessageType::Inv => Payload::Inv(Inv::deserialize(&mut payload_bytes)?),
+            MessageType::GetData => Payload::GetData(GetData::deserialize(&mut payload_bytes)?),
+            MessageType::NotFound => Payload::NotFound(NotFound::deserialize(&mut payload_bytes)?),
+            MessageType::Headers => Payload::Headers(Headers::deserialize(&mut payload_bytes)?),
+            MessageType::GetHeaders => {
+                Payload::GetHeaders(GetHeaders::deserialize(&mut payload_bytes)?)
+            }
+            MessageType::GetBlocks => {
+                Payload::GetBlocks(GetBlocks::deserialize(&mut payload_bytes)?)
+            }
+            MessageType::Tx => Payload::Tx(Transaction::deserialize(&mut payload_bytes)?),
+            MessageType::Block => Payload::Block(Block::deserialize(&mut payload_bytes)?),
+            MessageType::Reject => Payload::Reject(Reject::deserialize(&mut payload_bytes)?),
+            _ => Payload::Raw(payload_bytes.to_vec()),
         };
 
         Ok(Message {
@@ -211,14 +339,18 @@ mod test {
             .build()
             .unwrap();
 
-        let message = Message::build(Payload::Version(version), MessageType::Version, true);
+        let message = Message::build(
+            Payload::Version(version),
+            MessageType::Version,
+            &Network::Testnet,
+        );
 
         // Serialize the Message into a Vec<u8>
         let mut serialized_bytes = message.serialize().expect("serialize");
 
         // Deserialize the bytes back to Message
         let deserialized: Message =
-            Message::deserialize(&mut serialized_bytes.as_mut_slice()).expect("deserialize");
+            Message::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
 
         // Assert that the deserialized value matches the original value
         assert_eq!(deserialized, message);
@@ -229,16 +361,58 @@ mod test {
         // Create a VerAck
         let verack = VerAck;
 
-        let message = Message::build(Payload::VerAck(verack), MessageType::VerAck, true);
+        let message = Message::build(
+            Payload::VerAck(verack),
+            MessageType::VerAck,
+            &Network::Testnet,
+        );
+
+        // Serialize the Message into a Vec<u8>
+        let mut serialized_bytes = message.serialize().expect("serialize");
+
+        // Deserialize the bytes back to Message
+        let deserialized: Message =
+            Message::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, message);
+    }
+
+    #[test]
+    fn test_ping() {
+        let message = Message::build(
+            Payload::Ping(Ping::new()),
+            MessageType::Ping,
+            &Network::Testnet,
+        );
 
         // Serialize the Message into a Vec<u8>
         let mut serialized_bytes = message.serialize().expect("serialize");
 
         // Deserialize the bytes back to Message
         let deserialized: Message =
-            Message::deserialize(&mut serialized_bytes.as_mut_slice()).expect("deserialize");
+            Message::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
 
         // Assert that the deserialized value matches the original value
         assert_eq!(deserialized, message);
     }
+
+    #[test]
+    fn test_unknown_message() {
+        let message = Message::build(
+            Payload::Raw(vec![0x01, 0x02, 0x03]),
+            MessageType::Unknown("sendpackages".to_string()),
+            &Network::Testnet,
+        );
+
+        // Serialize the Message into a Vec<u8>
+        let mut serialized_bytes = message.serialize().expect("serialize");
+
+        // Deserialize the bytes back to Message
+        let deserialized: Message =
+            Message::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the command and the payload are kept as received
+        assert_eq!(deserialized, message);
+    }
 }
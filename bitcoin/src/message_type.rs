@@ -1,7 +1,7 @@
 use crate::{SerdeBitcoin, SerdeBitcoinError};
 use strum_macros::{AsRefStr, Display, EnumString};
 
-#[derive(Clone, Debug, Display, AsRefStr, PartialEq, EnumString)]
+#[derive(Clone, Debug, Display, AsRefStr, PartialEq, Eq, Hash, EnumString)]
 pub enum MessageType {
     #[strum(serialize = "version")]
     Version,
@@ -9,10 +9,20 @@ pub enum MessageType {
     VerAck,
     #[strum(serialize = "ping")]
     Ping,
+    #[strum(serialize = "pong")]
+    Pong,
     #[strum(serialize = "addr")]
     Addr,
+    #[strum(serialize = "addrv2")]
+    AddrV2,
+    #[strum(serialize = "getaddr")]
+    GetAddr,
+    #[strum(serialize = "inv")]
+    Inv,
     #[strum(serialize = "getdata")]
     GetData,
+    #[strum(serialize = "notfound")]
+    NotFound,
     #[strum(serialize = "tx")]
     Tx,
     #[strum(serialize = "block")]
@@ -37,10 +47,15 @@ pub enum MessageType {
     WtxIdRelay,
     #[strum(serialize = "sendaddrv2")]
     SendAddrV2,
+    #[strum(serialize = "sendtxrcncl")]
+    SendTxRcncl,
+    /// Any other command, kept as received
+    #[strum(default)]
+    Unknown(String),
 }
 
 impl MessageType {
-    const SIZE: usize = 12;
+    pub const SIZE: usize = 12;
 }
 
 impl SerdeBitcoin for MessageType {
@@ -60,18 +75,22 @@ impl SerdeBitcoin for MessageType {
             return Err(SerdeBitcoinError::MessageTypeTooLong(data.len()));
         }
 
-        // Calculate the number of zeroes needed to fill the remaining space
-        let zeroes_count = data.iter().rev().take_while(|&&byte| byte == 0).count();
-
-        // Create the MessageType variant
-        if let Some(version) = data.get(0..(data.len() - zeroes_count)) {
-            let version_str = String::from_utf8_lossy(version);
-            version_str
-                .parse::<MessageType>()
-                .map_err(|_| SerdeBitcoinError::UnknownType(version_str.to_string()))
-        } else {
-            Err(SerdeBitcoinError::Infallible)
+        // Like Bitcoin Core, the command is printable ASCII padded with zeroes
+        let length = data
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(data.len());
+        let (command, padding) = data.split_at(length);
+        if !command.iter().all(|byte| (0x20..=0x7e).contains(byte))
+            || padding.iter().any(|&byte| byte != 0)
+        {
+            return Err(SerdeBitcoinError::InvalidMessageType(data.to_vec()));
         }
+
+        let command: String = command.iter().map(|&byte| char::from(byte)).collect();
+        command
+            .parse::<MessageType>()
+            .map_err(|_| SerdeBitcoinError::UnknownType(command))
     }
 }
 
@@ -100,9 +119,47 @@ mod test {
 
         // Deserialize the bytes back to MessageType
         let deserialized: MessageType =
-            MessageType::deserialize(&mut serialized_bytes.as_mut_slice()).expect("deserialize");
+            MessageType::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
 
         // Assert that the deserialized value matches the original value
         assert_eq!(deserialized, message_type);
     }
+
+    #[test]
+    fn test_message_type_unknown() {
+        let mut serialized_bytes = *b"sendpackages";
+
+        // Deserialize the bytes to MessageType
+        let deserialized: MessageType =
+            MessageType::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the command is kept as received
+        assert_eq!(
+            deserialized,
+            MessageType::Unknown("sendpackages".to_string())
+        );
+        assert_eq!(deserialized.to_string(), "sendpackages");
+
+        // Serialize it back and compare it with the original bytes
+        assert_eq!(
+            deserialized.serialize().expect("serialize"),
+            serialized_bytes
+        );
+    }
+
+    #[test]
+    fn test_message_type_invalid() {
+        // Not ASCII, a control character and data after the padding are all rejected
+        for bytes in [
+            *b"caf\xc3\xa9\0\0\0\0\0\0\0",
+            *b"ping\n\0\0\0\0\0\0\0",
+            *b"ping\0\0\0\0\0\0\0x",
+        ] {
+            let mut serialized_bytes = bytes;
+            assert!(matches!(
+                MessageType::deserialize(serialized_bytes.as_mut_slice()),
+                Err(SerdeBitcoinError::InvalidMessageType(received)) if received == bytes
+            ));
+        }
+    }
 }
@@ -0,0 +1,179 @@
+use crate::block_header::BlockHeader;
+use crate::encoding::WriteBitcoinExt;
+use crate::hash::{double_sha256, Hash256};
+use crate::MAGIC_BYTES_LENGTH;
+use serde::Deserialize;
+
+/// Magic bytes for mainnet
+const MAGIC_BYTES_MAINNET: [u8; MAGIC_BYTES_LENGTH] = [0xf9, 0xbe, 0xb4, 0xd9];
+
+/// Magic bytes for testnet3
+const MAGIC_BYTES_TESTNET: [u8; MAGIC_BYTES_LENGTH] = [0x0b, 0x11, 0x09, 0x07];
+
+/// Magic bytes for testnet4
+const MAGIC_BYTES_TESTNET4: [u8; MAGIC_BYTES_LENGTH] = [0x1c, 0x16, 0x3f, 0x28];
+
+/// Magic bytes for the default signet
+const MAGIC_BYTES_SIGNET: [u8; MAGIC_BYTES_LENGTH] = [0x0a, 0x03, 0xcf, 0x40];
+
+/// Magic bytes for regtest
+const MAGIC_BYTES_REGTEST: [u8; MAGIC_BYTES_LENGTH] = [0xfa, 0xbf, 0xb5, 0xda];
+
+const DNS_SEEDS_MAINNET: &[&str] = &[
+    "seed.bitcoin.sipa.be",
+    "dnsseed.bluematt.me",
+    "seed.bitcoinstats.com",
+    "seed.bitcoin.jonasschnelli.ch",
+    "seed.btc.petertodd.net",
+    "seed.bitcoin.sprovoost.nl",
+    "dnsseed.emzy.de",
+    "seed.bitcoin.wiz.biz",
+];
+
+const DNS_SEEDS_TESTNET: &[&str] = &[
+    "testnet-seed.bitcoin.jonasschnelli.ch",
+    "seed.tbtc.petertodd.net",
+    "seed.testnet.bitcoin.sprovoost.nl",
+    "testnet-seed.bluematt.me",
+];
+
+const DNS_SEEDS_TESTNET4: &[&str] = &[
+    "seed.testnet4.bitcoin.sprovoost.nl",
+    "seed.testnet4.wiz.biz",
+];
+
+const DNS_SEEDS_SIGNET: &[&str] = &[
+    "seed.signet.bitcoin.sprovoost.nl",
+    "seed.signet.achownodes.xyz",
+];
+
+/// Merkle root of the genesis block shared by mainnet, testnet3, signet and regtest
+const GENESIS_MERKLE_ROOT: &str =
+    "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+/// Merkle root of the testnet4 genesis block
+const GENESIS_MERKLE_ROOT_TESTNET4: &str =
+    "7aa0a7ae1e223414cb807e40cd57e667b718e42aaf9306db9102fe28912b7b4e";
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum Network {
+    Mainnet,
+    /// Testnet3
+    Testnet,
+    Testnet4,
+    /// Default signet
+    Signet,
+    /// Signet with a custom challenge script, hex encoded in the configuration
+    CustomSignet(#[serde(with = "hex::serde")] Vec<u8>),
+    Regtest,
+}
+
+impl Network {
+    /// Same name as in the configuration
+    pub fn name(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Testnet4 => "testnet4",
+            Network::Signet => "signet",
+            Network::CustomSignet(_) => "custom_signet",
+            Network::Regtest => "regtest",
+        }
+    }
+
+    pub fn magic_bytes(&self) -> [u8; MAGIC_BYTES_LENGTH] {
+        match self {
+            Network::Mainnet => MAGIC_BYTES_MAINNET,
+            Network::Testnet => MAGIC_BYTES_TESTNET,
+            Network::Testnet4 => MAGIC_BYTES_TESTNET4,
+            Network::Signet => MAGIC_BYTES_SIGNET,
+            Network::CustomSignet(challenge) => signet_magic_bytes(challenge),
+            Network::Regtest => MAGIC_BYTES_REGTEST,
+        }
+    }
+
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Network::Mainnet => 8333,
+            Network::Testnet => 18333,
+            Network::Testnet4 => 48333,
+            Network::Signet | Network::CustomSignet(_) => 38333,
+            Network::Regtest => 18444,
+        }
+    }
+
+    pub fn dns_seeds(&self) -> &'static [&'static str] {
+        match self {
+            Network::Mainnet => DNS_SEEDS_MAINNET,
+            Network::Testnet => DNS_SEEDS_TESTNET,
+            Network::Testnet4 => DNS_SEEDS_TESTNET4,
+            Network::Signet => DNS_SEEDS_SIGNET,
+            Network::CustomSignet(_) | Network::Regtest => &[],
+        }
+    }
+
+    /// Highest target allowed, in its compact representation
+    pub fn pow_limit_bits(&self) -> u32 {
+        match self {
+            Network::Mainnet | Network::Testnet | Network::Testnet4 => 0x1d00ffff,
+            Network::Signet | Network::CustomSignet(_) => 0x1e0377ae,
+            Network::Regtest => 0x207fffff,
+        }
+    }
+
+    /// Header of the first block of the chain, every signet shares the same one
+    pub fn genesis_block_header(&self) -> BlockHeader {
+        let (merkle_root, time, bits, nonce) = match self {
+            Network::Mainnet => (GENESIS_MERKLE_ROOT, 1231006505, 0x1d00ffff, 2083236893),
+            Network::Testnet => (GENESIS_MERKLE_ROOT, 1296688602, 0x1d00ffff, 414098458),
+            Network::Testnet4 => (
+                GENESIS_MERKLE_ROOT_TESTNET4,
+                1714777860,
+                0x1d00ffff,
+                393743547,
+            ),
+            Network::Signet | Network::CustomSignet(_) => {
+                (GENESIS_MERKLE_ROOT, 1598918400, 0x1e0377ae, 52613770)
+            }
+            Network::Regtest => (GENESIS_MERKLE_ROOT, 1296688602, 0x207fffff, 2),
+        };
+
+        BlockHeader::new(
+            1,
+            Hash256::ZERO,
+            merkle_root.parse().expect("Valid merkle root"),
+            time,
+            bits,
+            nonce,
+        )
+    }
+}
+
+/// The signet magic bytes are the first 4 bytes of the double SHA256 of the serialized challenge
+fn signet_magic_bytes(challenge: &[u8]) -> [u8; MAGIC_BYTES_LENGTH] {
+    let mut data = Vec::with_capacity(challenge.len() + 9);
+    data.write_var_bytes(challenge)
+        .expect("Writing to a Vec never fails");
+
+    let mut magic_bytes = [0u8; MAGIC_BYTES_LENGTH];
+    magic_bytes.copy_from_slice(&double_sha256(&data)[..MAGIC_BYTES_LENGTH]);
+    magic_bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Challenge of the default signet
+    const SIGNET_CHALLENGE: &str = "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae";
+
+    #[test]
+    fn test_custom_signet_magic_bytes() {
+        // The default challenge must produce the default signet magic bytes
+        let challenge = hex::decode(SIGNET_CHALLENGE).unwrap();
+        let network = Network::CustomSignet(challenge);
+
+        assert_eq!(network.magic_bytes(), Network::Signet.magic_bytes());
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{SerdeBitcoin, SerdeBitcoinError};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use getset::Getters;
+use rand::random;
+use std::io::Cursor;
+
+/// Ping with the nonce introduced in BIP31
+#[derive(Getters, Debug, PartialEq)]
+pub struct Ping {
+    #[getset(get = "pub")]
+    nonce: u64,
+}
+
+impl Ping {
+    /// Builds a ping with a random nonce
+    pub fn new() -> Self {
+        Self {
+            nonce: random::<u64>(),
+        }
+    }
+}
+
+impl Default for Ping {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SerdeBitcoin for Ping {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::with_capacity(8);
+        result.write_u64::<LittleEndian>(self.nonce)?;
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<Ping, SerdeBitcoinError> {
+        let mut cursor = Cursor::new(data);
+        let nonce = cursor.read_u64::<LittleEndian>()?;
+        Ok(Ping { nonce })
+    }
+}
+
+/// Pong answering a ping, it carries the same nonce
+#[derive(Getters, Debug, PartialEq)]
+pub struct Pong {
+    #[getset(get = "pub")]
+    nonce: u64,
+}
+
+impl Pong {
+    pub fn new(nonce: u64) -> Self {
+        Self { nonce }
+    }
+}
+
+impl SerdeBitcoin for Pong {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::with_capacity(8);
+        result.write_u64::<LittleEndian>(self.nonce)?;
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<Pong, SerdeBitcoinError> {
+        let mut cursor = Cursor::new(data);
+        let nonce = cursor.read_u64::<LittleEndian>()?;
+        Ok(Pong { nonce })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ping() {
+        // Create a Ping
+        let ping = Ping::new();
+
+        // Serialize the Ping into a Vec<u8>
+        let mut serialized_bytes = ping.serialize().expect("serialize");
+
+        // Assert that the serialized bytes length is as expected
+        assert_eq!(serialized_bytes.len(), 8);
+
+        // Deserialize the bytes back to Ping
+        let deserialized: Ping =
+            Ping::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, ping);
+    }
+
+    #[test]
+    fn test_pong() {
+        // Create a Pong
+        let pong = Pong::new(*Ping::new().nonce());
+
+        // Serialize the Pong into a Vec<u8>
+        let mut serialized_bytes = pong.serialize().expect("serialize");
+
+        // Deserialize the bytes back to Pong
+        let deserialized: Pong =
+            Pong::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, pong);
+    }
+}
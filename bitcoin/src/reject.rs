@@ -0,0 +1,195 @@
+use crate::encoding::{ReadBitcoinExt, WriteBitcoinExt};
+use crate::hash::Hash256;
+use crate::{SerdeBitcoin, SerdeBitcoinError};
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use getset::Getters;
+use std::fmt;
+use std::io::{Cursor, Read};
+
+/// Reason code of a reject message (BIP61)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectCode {
+    Malformed,
+    Invalid,
+    Obsolete,
+    Duplicate,
+    NonStandard,
+    Dust,
+    InsufficientFee,
+    Checkpoint,
+    /// Code not known, kept as is
+    Unknown(u8),
+}
+
+impl From<u8> for RejectCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => RejectCode::Malformed,
+            0x10 => RejectCode::Invalid,
+            0x11 => RejectCode::Obsolete,
+            0x12 => RejectCode::Duplicate,
+            0x40 => RejectCode::NonStandard,
+            0x41 => RejectCode::Dust,
+            0x42 => RejectCode::InsufficientFee,
+            0x43 => RejectCode::Checkpoint,
+            x => RejectCode::Unknown(x),
+        }
+    }
+}
+
+impl From<RejectCode> for u8 {
+    fn from(value: RejectCode) -> Self {
+        match value {
+            RejectCode::Malformed => 0x01,
+            RejectCode::Invalid => 0x10,
+            RejectCode::Obsolete => 0x11,
+            RejectCode::Duplicate => 0x12,
+            RejectCode::NonStandard => 0x40,
+            RejectCode::Dust => 0x41,
+            RejectCode::InsufficientFee => 0x42,
+            RejectCode::Checkpoint => 0x43,
+            RejectCode::Unknown(x) => x,
+        }
+    }
+}
+
+impl fmt::Display for RejectCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectCode::Malformed => write!(f, "malformed"),
+            RejectCode::Invalid => write!(f, "invalid"),
+            RejectCode::Obsolete => write!(f, "obsolete"),
+            RejectCode::Duplicate => write!(f, "duplicate"),
+            RejectCode::NonStandard => write!(f, "nonstandard"),
+            RejectCode::Dust => write!(f, "dust"),
+            RejectCode::InsufficientFee => write!(f, "insufficientfee"),
+            RejectCode::Checkpoint => write!(f, "checkpoint"),
+            RejectCode::Unknown(x) => write!(f, "unknown({x:#04x})"),
+        }
+    }
+}
+
+/// Sent by peers implementing BIP61 when they reject a message, removed from Bitcoin Core 0.20
+#[derive(Getters, Debug, Clone, PartialEq)]
+pub struct Reject {
+    /// Type of the rejected message
+    #[getset(get = "pub")]
+    message: String,
+
+    #[getset(get = "pub")]
+    code: RejectCode,
+
+    #[getset(get = "pub")]
+    reason: String,
+
+    /// Hash of the rejected transaction or block
+    #[getset(get = "pub")]
+    hash: Option<Hash256>,
+}
+
+impl Reject {
+    pub fn new(message: String, code: RejectCode, reason: String, hash: Option<Hash256>) -> Self {
+        Self {
+            message,
+            code,
+            reason,
+            hash,
+        }
+    }
+}
+
+impl fmt::Display for Reject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} rejected ({}): {}",
+            self.message, self.code, self.reason
+        )?;
+        if let Some(hash) = &self.hash {
+            write!(f, " {hash}")?;
+        }
+        Ok(())
+    }
+}
+
+impl SerdeBitcoin for Reject {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::new();
+        result.write_var_str(&self.message)?;
+        result.write_u8(self.code.into())?;
+        result.write_var_str(&self.reason)?;
+        if let Some(hash) = &self.hash {
+            result.write_hash(hash)?;
+        }
+
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<Reject, SerdeBitcoinError> {
+        let mut cursor = Cursor::new(data);
+        let message = cursor.read_var_str()?;
+        let code = cursor.read_u8()?.into();
+        let reason = cursor.read_var_str()?;
+
+        // The extra data is only defined as a hash, anything else is ignored
+        let mut extra = Vec::new();
+        cursor.read_to_end(&mut extra)?;
+        let hash = match <[u8; 32]>::try_from(extra) {
+            Ok(bytes) => Some(Hash256::new(bytes)),
+            Err(_) => None,
+        };
+
+        Ok(Reject {
+            message,
+            code,
+            reason,
+            hash,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reject() {
+        // Create a Reject
+        let reject = Reject::new(
+            "tx".to_string(),
+            RejectCode::InsufficientFee,
+            "min relay fee not met".to_string(),
+            Some(Hash256::new([7; 32])),
+        );
+
+        // Serialize the Reject into a Vec<u8>
+        let mut serialized_bytes = reject.serialize().expect("serialize");
+
+        // Assert that the serialized bytes length is as expected
+        assert_eq!(serialized_bytes.len(), 3 + 1 + 22 + 32);
+
+        // Deserialize the bytes back to Reject
+        let deserialized: Reject =
+            Reject::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the deserialized value matches the original value
+        assert_eq!(deserialized, reject);
+    }
+
+    #[test]
+    fn test_reject_without_hash() {
+        // Reject of a version message, as sent by old peers
+        let mut serialized_bytes = vec![0x07];
+        serialized_bytes.extend(b"version");
+        serialized_bytes.push(0x11);
+        serialized_bytes.push(0x08);
+        serialized_bytes.extend(b"obsolete");
+
+        let reject = Reject::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        assert_eq!(reject.message(), "version");
+        assert_eq!(*reject.code(), RejectCode::Obsolete);
+        assert_eq!(reject.reason(), "obsolete");
+        assert_eq!(*reject.hash(), None);
+    }
+}
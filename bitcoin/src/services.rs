@@ -0,0 +1,80 @@
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+bitflags! {
+    /// Services offered by a node, announced in its version and address messages.
+    ///
+    /// With serde the flags are written as their names separated by `|`, like
+    /// `NODE_NETWORK | NODE_WITNESS`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub struct ServiceFlags: u64 {
+        /// Serves the full block chain
+        const NODE_NETWORK = 1 << 0;
+        /// Answers getutxo requests (BIP64)
+        const NODE_GETUTXO = 1 << 1;
+        /// Supports bloom filtered connections (BIP111)
+        const NODE_BLOOM = 1 << 2;
+        /// Serves blocks and transactions with witness data (BIP144)
+        const NODE_WITNESS = 1 << 3;
+        /// Serves compact block filters (BIP157)
+        const NODE_COMPACT_FILTERS = 1 << 6;
+        /// Serves the last 288 blocks (BIP159)
+        const NODE_NETWORK_LIMITED = 1 << 10;
+        /// Supports the v2 encrypted transport (BIP324)
+        const NODE_P2P_V2 = 1 << 11;
+
+        // Bits not known are kept as received
+        const _ = !0;
+    }
+}
+
+impl Default for ServiceFlags {
+    fn default() -> Self {
+        ServiceFlags::empty()
+    }
+}
+
+impl fmt::Display for ServiceFlags {
+    /// Flag names separated by `|`, unknown bits are written in hex
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "NONE");
+        }
+        bitflags::parser::to_writer(self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_service_flags() {
+        let services = ServiceFlags::from_bits_retain(0x0c09);
+
+        // Assert that the known flags are decoded
+        assert!(services.contains(ServiceFlags::NODE_NETWORK | ServiceFlags::NODE_WITNESS));
+        assert!(!services.contains(ServiceFlags::NODE_BLOOM));
+        assert_eq!(
+            services.to_string(),
+            "NODE_NETWORK | NODE_WITNESS | NODE_NETWORK_LIMITED | NODE_P2P_V2"
+        );
+        assert_eq!(ServiceFlags::empty().to_string(), "NONE");
+    }
+
+    #[test]
+    fn test_unknown_service_flags() {
+        // Bit 24 is not assigned
+        let services = ServiceFlags::from_bits_retain(1 << 24 | 1);
+
+        // Assert that the unknown bit is kept and displayed
+        assert_eq!(services.bits(), 1 << 24 | 1);
+        assert_eq!(services.to_string(), "NODE_NETWORK | 0x1000000");
+
+        // Assert that the flags survive a round trip through their text form
+        let parsed: ServiceFlags =
+            bitflags::parser::from_str(&services.to_string()).expect("parse");
+        assert_eq!(parsed, services);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::encoding::{ReadBitcoinExt, WriteBitcoinExt};
+use crate::hash::Hash256;
+use crate::{SerdeBitcoin, SerdeBitcoinError};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use getset::Getters;
+use std::io::{Cursor, Read, Write};
+
+/// Marker byte, in place of the inputs count, announcing the segwit serialization (BIP144)
+const SEGWIT_MARKER: u8 = 0x00;
+
+/// Only flag defined by BIP144
+const SEGWIT_FLAG: u8 = 0x01;
+
+/// Output spent by an input
+#[derive(Getters, Debug, Clone, PartialEq)]
+pub struct OutPoint {
+    #[getset(get = "pub")]
+    txid: Hash256,
+
+    #[getset(get = "pub")]
+    vout: u32,
+}
+
+impl OutPoint {
+    pub fn new(txid: Hash256, vout: u32) -> Self {
+        Self { txid, vout }
+    }
+}
+
+#[derive(Getters, Debug, Clone, PartialEq)]
+pub struct TxIn {
+    #[getset(get = "pub")]
+    previous_output: OutPoint,
+
+    #[getset(get = "pub")]
+    script_sig: Vec<u8>,
+
+    #[getset(get = "pub")]
+    sequence: u32,
+
+    /// Witness stack items, empty for non-segwit inputs
+    #[getset(get = "pub")]
+    witness: Vec<Vec<u8>>,
+}
+
+impl TxIn {
+    pub fn new(
+        previous_output: OutPoint,
+        script_sig: Vec<u8>,
+        sequence: u32,
+        witness: Vec<Vec<u8>>,
+    ) -> Self {
+        Self {
+            previous_output,
+            script_sig,
+            sequence,
+            witness,
+        }
+    }
+}
+
+#[derive(Getters, Debug, Clone, PartialEq)]
+pub struct TxOut {
+    /// Amount in satoshis
+    #[getset(get = "pub")]
+    value: i64,
+
+    #[getset(get = "pub")]
+    script_pubkey: Vec<u8>,
+}
+
+impl TxOut {
+    pub fn new(value: i64, script_pubkey: Vec<u8>) -> Self {
+        Self {
+            value,
+            script_pubkey,
+        }
+    }
+}
+
+#[derive(Getters, Debug, Clone, PartialEq)]
+pub struct Transaction {
+    #[getset(get = "pub")]
+    version: i32,
+
+    #[getset(get = "pub")]
+    inputs: Vec<TxIn>,
+
+    #[getset(get = "pub")]
+    outputs: Vec<TxOut>,
+
+    #[getset(get = "pub")]
+    lock_time: u32,
+}
+
+impl Transaction {
+    pub fn new(version: i32, inputs: Vec<TxIn>, outputs: Vec<TxOut>, lock_time: u32) -> Self {
+        Self {
+            version,
+            inputs,
+            outputs,
+            lock_time,
+        }
+    }
+
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+
+    /// Hash of the serialization without witness data
+    pub fn txid(&self) -> Hash256 {
+        let mut data = Vec::new();
+        self.write(&mut data, false)
+            .expect("Writing to a Vec never fails");
+        Hash256::hash(&data)
+    }
+
+    /// Hash of the serialization with witness data, same as the txid without witnesses
+    pub fn wtxid(&self) -> Hash256 {
+        let mut data = Vec::new();
+        self.write(&mut data, true)
+            .expect("Writing to a Vec never fails");
+        Hash256::hash(&data)
+    }
+
+    /// Size in bytes without the witness data
+    pub fn base_size(&self) -> usize {
+        let mut data = Vec::new();
+        self.write(&mut data, false)
+            .expect("Writing to a Vec never fails");
+        data.len()
+    }
+
+    /// Size in bytes with the witness data
+    pub fn total_size(&self) -> usize {
+        let mut data = Vec::new();
+        self.write(&mut data, true)
+            .expect("Writing to a Vec never fails");
+        data.len()
+    }
+
+    /// Weight units as defined by BIP141
+    pub fn weight(&self) -> usize {
+        self.base_size() * 3 + self.total_size()
+    }
+
+    pub(crate) fn read<R: Read + ?Sized>(reader: &mut R) -> Result<Self, SerdeBitcoinError> {
+        let version = reader.read_i32::<LittleEndian>()?;
+
+        let mut inputs_count = reader.read_compact_size()?;
+        let segwit = inputs_count == u64::from(SEGWIT_MARKER);
+        if segwit {
+            let flag = reader.read_u8()?;
+            if flag != SEGWIT_FLAG {
+                return Err(SerdeBitcoinError::InvalidSegwitFlag(flag));
+            }
+            inputs_count = reader.read_compact_size()?;
+        }
+
+        let mut inputs = (0..inputs_count)
+            .map(|_| {
+                Ok(TxIn {
+                    previous_output: OutPoint {
+                        txid: reader.read_hash()?,
+                        vout: reader.read_u32::<LittleEndian>()?,
+                    },
+                    script_sig: reader.read_var_bytes()?,
+                    sequence: reader.read_u32::<LittleEndian>()?,
+                    witness: Vec::new(),
+                })
+            })
+            .collect::<Result<Vec<_>, SerdeBitcoinError>>()?;
+
+        let outputs_count = reader.read_compact_size()?;
+        let outputs = (0..outputs_count)
+            .map(|_| {
+                Ok(TxOut {
+                    value: reader.read_i64::<LittleEndian>()?,
+                    script_pubkey: reader.read_var_bytes()?,
+                })
+            })
+            .collect::<Result<Vec<_>, SerdeBitcoinError>>()?;
+
+        if segwit {
+            for input in &mut inputs {
+                let items = reader.read_compact_size()?;
+                input.witness = (0..items)
+                    .map(|_| reader.read_var_bytes())
+                    .collect::<Result<_, _>>()?;
+            }
+        }
+
+        let transaction = Self {
+            version,
+            inputs,
+            outputs,
+            lock_time: reader.read_u32::<LittleEndian>()?,
+        };
+
+        // The segwit serialization is only valid when there are witnesses to carry
+        if segwit && !transaction.has_witness() {
+            return Err(SerdeBitcoinError::SuperfluousWitness);
+        }
+
+        Ok(transaction)
+    }
+
+    pub(crate) fn write<W: Write + ?Sized>(
+        &self,
+        writer: &mut W,
+        include_witness: bool,
+    ) -> Result<(), SerdeBitcoinError> {
+        let segwit = include_witness && self.has_witness();
+
+        writer.write_i32::<LittleEndian>(self.version)?;
+        if segwit {
+            writer.write_u8(SEGWIT_MARKER)?;
+            writer.write_u8(SEGWIT_FLAG)?;
+        }
+
+        writer.write_compact_size(self.inputs.len() as u64)?;
+        for input in &self.inputs {
+            writer.write_hash(&input.previous_output.txid)?;
+            writer.write_u32::<LittleEndian>(input.previous_output.vout)?;
+            writer.write_var_bytes(&input.script_sig)?;
+            writer.write_u32::<LittleEndian>(input.sequence)?;
+        }
+
+        writer.write_compact_size(self.outputs.len() as u64)?;
+        for output in &self.outputs {
+            writer.write_i64::<LittleEndian>(output.value)?;
+            writer.write_var_bytes(&output.script_pubkey)?;
+        }
+
+        if segwit {
+            for input in &self.inputs {
+                writer.write_compact_size(input.witness.len() as u64)?;
+                for item in &input.witness {
+                    writer.write_var_bytes(item)?;
+                }
+            }
+        }
+
+        writer.write_u32::<LittleEndian>(self.lock_time)?;
+        Ok(())
+    }
+}
+
+impl SerdeBitcoin for Transaction {
+    fn serialize(&self) -> Result<Vec<u8>, SerdeBitcoinError> {
+        let mut result = Vec::new();
+        self.write(&mut result, true)?;
+        Ok(result)
+    }
+
+    fn deserialize(data: &mut [u8]) -> Result<Transaction, SerdeBitcoinError> {
+        Transaction::read(&mut Cursor::new(data))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::GENESIS_COINBASE;
+
+    /// Signed native P2WPKH example from BIP143
+    const SEGWIT_TRANSACTION: &str = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+
+    #[test]
+    fn test_transaction() {
+        let mut serialized_bytes = hex::decode(GENESIS_COINBASE).unwrap();
+
+        // Deserialize the bytes to a Transaction
+        let transaction =
+            Transaction::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that the fields are the expected ones
+        assert_eq!(transaction.inputs().len(), 1);
+        assert_eq!(transaction.outputs().len(), 1);
+        assert_eq!(*transaction.outputs()[0].value(), 50 * 100_000_000);
+        assert!(!transaction.has_witness());
+
+        // Assert that the txid is the merkle root of the genesis block
+        assert_eq!(
+            transaction.txid().to_string(),
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+        );
+        assert_eq!(transaction.wtxid(), transaction.txid());
+
+        // Serialize the Transaction back and compare it with the original bytes
+        assert_eq!(
+            transaction.serialize().expect("serialize"),
+            serialized_bytes
+        );
+    }
+
+    #[test]
+    fn test_segwit_transaction() {
+        let mut serialized_bytes = hex::decode(SEGWIT_TRANSACTION).unwrap();
+
+        // Deserialize the bytes to a Transaction
+        let transaction =
+            Transaction::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
+
+        // Assert that only the second input has a witness
+        assert_eq!(transaction.inputs().len(), 2);
+        assert!(transaction.inputs()[0].witness().is_empty());
+        assert_eq!(transaction.inputs()[1].witness().len(), 2);
+        assert_eq!(transaction.outputs().len(), 2);
+        assert_eq!(*transaction.lock_time(), 17);
+
+        // Assert that the witness data is only part of the wtxid
+        assert_ne!(transaction.txid(), transaction.wtxid());
+        assert_eq!(transaction.total_size(), serialized_bytes.len());
+        assert_eq!(
+            transaction.weight(),
+            transaction.base_size() * 3 + serialized_bytes.len()
+        );
+
+        // Serialize the Transaction back and compare it with the original bytes
+        assert_eq!(
+            transaction.serialize().expect("serialize"),
+            serialized_bytes
+        );
+    }
+
+    #[test]
+    fn test_superfluous_witness() {
+        // Segwit serialization of the genesis coinbase with an empty witness
+        let mut transaction = hex::decode(GENESIS_COINBASE).unwrap();
+        let lock_time = transaction.split_off(transaction.len() - 4);
+        transaction.splice(4..4, [SEGWIT_MARKER, SEGWIT_FLAG]);
+        transaction.push(0x00);
+        transaction.extend(lock_time);
+
+        assert!(matches!(
+            Transaction::deserialize(transaction.as_mut_slice()),
+            Err(SerdeBitcoinError::SuperfluousWitness)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_segwit_flag() {
+        let mut transaction = hex::decode(SEGWIT_TRANSACTION).unwrap();
+        transaction[5] = 0x02;
+
+        assert!(matches!(
+            Transaction::deserialize(transaction.as_mut_slice()),
+            Err(SerdeBitcoinError::InvalidSegwitFlag(0x02))
+        ));
+    }
+}
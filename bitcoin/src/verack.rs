@@ -27,7 +27,7 @@ mod test {
 
         // Deserialize the bytes back to VerAck
         let deserialized: VerAck =
-            VerAck::deserialize(&mut serialized_bytes.as_mut_slice()).expect("deserialize");
+            VerAck::deserialize(serialized_bytes.as_mut_slice()).expect("deserialize");
 
         // Assert that the deserialized value matches the original value
         assert_eq!(deserialized, verack);
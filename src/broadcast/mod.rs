@@ -0,0 +1,398 @@
+use bitcoin::codec::MessageCodec;
+use bitcoin::hash::Hash256;
+use bitcoin::inventory::{Inv, InvType, InvVector};
+use bitcoin::message_type::MessageType;
+use bitcoin::network::Network;
+use bitcoin::ping::{Ping, Pong};
+use bitcoin::transaction::Transaction;
+use bitcoin::{Message, Payload, SerdeBitcoin, SerdeBitcoinError};
+use futures::{SinkExt, StreamExt};
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio::time::{sleep_until, Instant};
+use tokio_util::codec::Framed;
+use tracing::info;
+
+/// Time given to the peer to request and process the announced transactions
+pub const BROADCAST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a peer did with an announced transaction
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Never requested
+    Ignored,
+    /// Requested and not rejected
+    Requested,
+    /// Rejected with the reason sent by the peer
+    Rejected(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Ignored => write!(f, "ignored"),
+            Outcome::Requested => write!(f, "requested"),
+            Outcome::Rejected(reason) => write!(f, "rejected: {reason}"),
+        }
+    }
+}
+
+/// Parses one hex encoded transaction per line, empty lines are skipped
+pub fn parse_transactions(content: &str) -> Result<Vec<Transaction>, Error> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let mut bytes =
+                hex::decode(line.trim()).map_err(|e| Error::InvalidHex(index + 1, e))?;
+            Transaction::deserialize(&mut bytes)
+                .map_err(|e| Error::InvalidTransaction(index + 1, e))
+        })
+        .collect()
+}
+
+/// Announces the transactions and serves them when the peer requests them.
+///
+/// Once every transaction was requested a ping is sent, since peers process the messages in
+/// order the pong means that any reject was already received. The transactions not requested
+/// before `wait` expires are reported as ignored.
+///
+/// When both sides sent wtxidrelay the transactions are announced by wtxid, peers ignore the
+/// announcements by txid after that negotiation (BIP339).
+pub async fn run(
+    framed: &mut Framed<TcpStream, MessageCodec>,
+    addr: &SocketAddr,
+    network: &Network,
+    transactions: &[Transaction],
+    wtxid_relay: bool,
+    wait: Duration,
+) -> Result<Vec<(Hash256, Outcome)>, Error> {
+    let txids: Vec<Hash256> = transactions.iter().map(Transaction::txid).collect();
+    let wtxids: Vec<Hash256> = transactions.iter().map(Transaction::wtxid).collect();
+    let mut outcomes: Vec<(Hash256, Outcome)> =
+        txids.iter().map(|txid| (*txid, Outcome::Ignored)).collect();
+
+    let inventory = if wtxid_relay {
+        wtxids
+            .iter()
+            .map(|wtxid| InvVector::new(InvType::Wtx, *wtxid))
+            .collect()
+    } else {
+        txids
+            .iter()
+            .map(|txid| InvVector::new(InvType::Tx, *txid))
+            .collect()
+    };
+    framed
+        .send(Message::build(
+            Payload::Inv(Inv::new(inventory)),
+            MessageType::Inv,
+            network,
+        ))
+        .await
+        .map_err(Error::SendInv)?;
+
+    let deadline = Instant::now() + wait;
+    let mut pending_ping: Option<u64> = None;
+    loop {
+        let message = tokio::select! {
+            message = framed.next() => message,
+            _ = sleep_until(deadline) => return Ok(outcomes),
+        };
+        let message = message
+            .ok_or(Error::ConnectionClosed)?
+            .map_err(Error::Deserialize)?;
+
+        match message.payload() {
+            Payload::GetData(get_data) => {
+                let mut served = false;
+                for entry in get_data.inventory() {
+                    let Some(index) = txids
+                        .iter()
+                        .position(|txid| txid == entry.hash())
+                        .or_else(|| wtxids.iter().position(|wtxid| wtxid == entry.hash()))
+                    else {
+                        continue;
+                    };
+                    framed
+                        .send(Message::build(
+                            Payload::Tx(transactions[index].clone()),
+                            MessageType::Tx,
+                            network,
+                        ))
+                        .await
+                        .map_err(Error::SendTx)?;
+                    info!("{addr} requested tx {}", txids[index]);
+                    if outcomes[index].1 == Outcome::Ignored {
+                        outcomes[index].1 = Outcome::Requested;
+                    }
+                    served = true;
+                }
+
+                if served
+                    && outcomes
+                        .iter()
+                        .all(|(_, outcome)| *outcome != Outcome::Ignored)
+                {
+                    let ping = Ping::new();
+                    pending_ping = Some(*ping.nonce());
+                    framed
+                        .send(Message::build(
+                            Payload::Ping(ping),
+                            MessageType::Ping,
+                            network,
+                        ))
+                        .await
+                        .map_err(Error::SendPing)?;
+                }
+            }
+            Payload::Reject(reject) => {
+                let rejected = reject
+                    .hash()
+                    .and_then(|hash| outcomes.iter_mut().find(|(txid, _)| *txid == hash));
+                if let Some((_, outcome)) = rejected {
+                    info!("{addr} sent {reject}");
+                    *outcome =
+                        Outcome::Rejected(format!("{} ({})", reject.reason(), reject.code()));
+                }
+            }
+            Payload::Pong(pong) if pending_ping == Some(*pong.nonce()) => return Ok(outcomes),
+            Payload::Ping(ping) => {
+                let pong = Pong::new(*ping.nonce());
+                framed
+                    .send(Message::build(
+                        Payload::Pong(pong),
+                        MessageType::Pong,
+                        network,
+                    ))
+                    .await
+                    .map_err(Error::SendPong)?;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid hex in line {0}")]
+    InvalidHex(usize, #[source] hex::FromHexError),
+    #[error("Invalid transaction in line {0}")]
+    InvalidTransaction(usize, #[source] SerdeBitcoinError),
+    #[error("Failed to send the inv message")]
+    SendInv(#[source] SerdeBitcoinError),
+    #[error("Failed to send the tx message")]
+    SendTx(#[source] SerdeBitcoinError),
+    #[error("Failed to send the ping message")]
+    SendPing(#[source] SerdeBitcoinError),
+    #[error("Failed to send the pong message")]
+    SendPong(#[source] SerdeBitcoinError),
+    #[error("Failed to deserialize the message")]
+    Deserialize(#[source] SerdeBitcoinError),
+    #[error("Connection closed by the peer")]
+    ConnectionClosed,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{sender, test_peer};
+    use bitcoin::codec::PayloadLimits;
+    use bitcoin::inventory::GetData;
+    use bitcoin::reject::{Reject, RejectCode};
+    use bitcoin::transaction::{OutPoint, TxIn, TxOut};
+    use std::sync::Arc;
+
+    fn transaction(vout: u32) -> Transaction {
+        Transaction::new(
+            2,
+            vec![TxIn::new(
+                OutPoint::new(Hash256::new([1; 32]), vout),
+                vec![0x51],
+                0xffff_ffff,
+                vec![vec![0x01]],
+            )],
+            vec![TxOut::new(1000, vec![0x51])],
+            0,
+        )
+    }
+
+    #[test]
+    fn test_parse_transactions() {
+        let tx = transaction(0);
+        let content = format!("\n{}\n\n", hex::encode(tx.serialize().unwrap()));
+
+        assert_eq!(parse_transactions(&content).expect("parse"), vec![tx]);
+        assert!(matches!(
+            parse_transactions("0100zz"),
+            Err(Error::InvalidHex(1, _))
+        ));
+        assert!(matches!(
+            parse_transactions("\n0100"),
+            Err(Error::InvalidTransaction(2, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast() {
+        let network = Arc::new(Network::Regtest);
+        let transactions = vec![transaction(0), transaction(1), transaction(2)];
+        let txids: Vec<Hash256> = transactions.iter().map(Transaction::txid).collect();
+
+        // Stand-in peer requesting the first two transactions and rejecting the second one
+        let (listener, addr) = test_peer::bind().await;
+        let requested = txids[..2].to_vec();
+        let peer = tokio::spawn(async move {
+            let network = Network::Regtest;
+            let mut framed = test_peer::accept(&listener, &network).await;
+            loop {
+                let message = test_peer::receive(&mut framed).await;
+                let (payload, ty) = match message.payload() {
+                    Payload::Inv(inv) => {
+                        // Announced by txid without wtxidrelay
+                        assert!(inv
+                            .inventory()
+                            .iter()
+                            .all(|entry| *entry.ty() == InvType::Tx));
+                        let inventory = requested
+                            .iter()
+                            .map(|txid| InvVector::new(InvType::WitnessTx, *txid))
+                            .collect();
+                        (
+                            Payload::GetData(GetData::new(inventory)),
+                            MessageType::GetData,
+                        )
+                    }
+                    Payload::Tx(tx) if tx.txid() == requested[1] => {
+                        let reject = Reject::new(
+                            "tx".to_string(),
+                            RejectCode::InsufficientFee,
+                            "min relay fee not met".to_string(),
+                            Some(tx.txid()),
+                        );
+                        (Payload::Reject(reject), MessageType::Reject)
+                    }
+                    Payload::Ping(ping) => {
+                        (Payload::Pong(Pong::new(*ping.nonce())), MessageType::Pong)
+                    }
+                    _ => continue,
+                };
+                test_peer::send(&mut framed, payload, ty, &network).await;
+            }
+        });
+
+        let (info, mut framed) = sender::run(
+            &addr,
+            network.clone(),
+            PayloadLimits::default(),
+            Arc::default(),
+            Arc::default(),
+        )
+        .await
+        .expect("handshake");
+        let outcomes = run(
+            &mut framed,
+            info.addr(),
+            &network,
+            &transactions,
+            false,
+            Duration::from_millis(500),
+        )
+        .await
+        .expect("broadcast");
+        peer.abort();
+
+        assert_eq!(
+            outcomes,
+            vec![
+                (txids[0], Outcome::Requested),
+                (
+                    txids[1],
+                    Outcome::Rejected("min relay fee not met (insufficientfee)".to_string())
+                ),
+                (txids[2], Outcome::Ignored),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_wtxid_relay() {
+        let network = Arc::new(Network::Regtest);
+        let transactions = vec![transaction(0), transaction(1)];
+        let txids: Vec<Hash256> = transactions.iter().map(Transaction::txid).collect();
+        let wtxids: Vec<Hash256> = transactions.iter().map(Transaction::wtxid).collect();
+
+        // Stand-in peer requesting every transaction announced by wtxid
+        let (listener, addr) = test_peer::bind().await;
+        let peer = tokio::spawn(async move {
+            let network = Network::Regtest;
+            let mut framed = test_peer::accept(&listener, &network).await;
+            let mut announced = Vec::new();
+            loop {
+                let message = test_peer::receive(&mut framed).await;
+                let (payload, ty) = match message.payload() {
+                    Payload::Inv(inv) => {
+                        announced = inv.inventory().clone();
+                        (
+                            Payload::GetData(GetData::new(announced.clone())),
+                            MessageType::GetData,
+                        )
+                    }
+                    Payload::Ping(ping) => {
+                        test_peer::send(
+                            &mut framed,
+                            Payload::Pong(Pong::new(*ping.nonce())),
+                            MessageType::Pong,
+                            &network,
+                        )
+                        .await;
+                        return announced;
+                    }
+                    _ => continue,
+                };
+                test_peer::send(&mut framed, payload, ty, &network).await;
+            }
+        });
+
+        let (info, mut framed) = sender::run(
+            &addr,
+            network.clone(),
+            PayloadLimits::default(),
+            Arc::default(),
+            Arc::default(),
+        )
+        .await
+        .expect("handshake");
+        let outcomes = run(
+            &mut framed,
+            info.addr(),
+            &network,
+            &transactions,
+            true,
+            Duration::from_secs(5),
+        )
+        .await
+        .expect("broadcast");
+        let announced = peer.await.expect("peer");
+
+        // Assert that the transactions were announced by wtxid and served when requested so
+        assert_eq!(
+            announced,
+            wtxids
+                .iter()
+                .map(|wtxid| InvVector::new(InvType::Wtx, *wtxid))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            outcomes,
+            vec![
+                (txids[0], Outcome::Requested),
+                (txids[1], Outcome::Requested)
+            ]
+        );
+    }
+}
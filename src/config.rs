@@ -1,26 +1,36 @@
-use clap::Parser;
+use crate::report::Format;
+use crate::sender::Limiter;
+use bitcoin::addr::SendAddrV2;
+use bitcoin::codec::PayloadLimits;
+use bitcoin::feature::{FeeFilter, SendCmpct, SendHeaders, WtxIdRelay};
+use bitcoin::hash::Hash256;
+use bitcoin::message_type::MessageType;
+use bitcoin::network::Network;
+use bitcoin::services::ServiceFlags;
+use bitcoin::version::{VersionBuilder, PROTOCOL_VERSION};
+use bitcoin::{Payload, MAX_PROTOCOL_MESSAGE_LENGTH};
+use clap::{Parser, ValueEnum};
 use serde::Deserialize;
-use std::path::Path;
+use std::collections::HashMap;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
+
 use thiserror::Error;
 
-#[derive(Clone, Debug, PartialEq, Deserialize)]
-#[serde(deny_unknown_fields, rename_all = "snake_case")]
-pub enum Network {
-    Mainnet,
-    Testnet,
-}
+/// Oldest protocol version Bitcoin Core connects to
+const MIN_PEER_PROTOCOL_VERSION: i32 = 31800;
 
-impl Network {
-    pub fn is_testnet(&self) -> bool {
-        match self {
-            Network::Mainnet => false,
-            Network::Testnet => true,
-        }
-    }
-}
+/// Protocol version that introduced wtxidrelay (BIP339)
+const WTXID_RELAY_VERSION: i32 = 70016;
+
+/// Same as Bitcoin Core's MAX_SUBVERSION_LENGTH
+const MAX_USER_AGENT_LENGTH: usize = 256;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
+    /// Version announced by the listener and the sender, each one can override any field
+    #[serde(default)]
+    pub version: VersionConfig,
     /// Listener configuration
     pub listener: Option<ListenerConfig>,
     /// Sender configuration
@@ -30,36 +40,391 @@ pub struct Config {
 impl Config {
     pub fn parse(path: &Path) -> Result<Self, Error> {
         let content = std::fs::read_to_string(path).map_err(|_| Error::File(path.into()))?;
-        let file = serde_yaml::from_str::<Self>(&content).map_err(|_| Error::File(path.into()))?;
+        let mut file =
+            serde_yaml::from_str::<Self>(&content).map_err(|_| Error::File(path.into()))?;
+        file.resolve()?;
         Ok(file)
     }
+
+    /// Applies the top level version to the listener and the sender, then validates them
+    fn resolve(&mut self) -> Result<(), Error> {
+        if let Some(listener) = &mut self.listener {
+            listener.handshake.version = listener.handshake.version.or(&self.version);
+            listener.handshake.validate()?;
+            listener.limits.payload_limits()?;
+            if let Some(ping) = &listener.ping {
+                ping.validate()?;
+            }
+        }
+        if let Some(sender) = &mut self.sender {
+            sender.handshake.version = sender.handshake.version.or(&self.version);
+            sender.handshake.validate()?;
+            sender.limits.payload_limits()?;
+            if let Some(ping) = &sender.ping {
+                ping.validate()?;
+            }
+            if sender.crawler.max_concurrency == 0 || sender.max_concurrent_handshakes == Some(0) {
+                return Err(Error::NoConcurrency);
+            }
+            if sender.connections_per_second == Some(0) {
+                return Err(Error::NoConnectionRate);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fields of the version message, the ones not set keep the defaults of `VersionBuilder`
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct VersionConfig {
+    /// Protocol version, at least 31800
+    pub protocol_version: Option<i32>,
+
+    /// Services offered to the peers, as flag names separated by `|`
+    pub services: Option<ServiceFlags>,
+
+    /// User agent, at most 256 bytes
+    pub user_agent: Option<String>,
+
+    /// Height of our best block
+    pub start_height: Option<i32>,
+
+    /// Ask the peers to relay transactions
+    pub relay: Option<bool>,
+}
+
+impl VersionConfig {
+    /// Takes every field not set from `defaults`
+    pub fn or(&self, defaults: &VersionConfig) -> VersionConfig {
+        VersionConfig {
+            protocol_version: self.protocol_version.or(defaults.protocol_version),
+            services: self.services.or(defaults.services),
+            user_agent: self
+                .user_agent
+                .clone()
+                .or_else(|| defaults.user_agent.clone()),
+            start_height: self.start_height.or(defaults.start_height),
+            relay: self.relay.or(defaults.relay),
+        }
+    }
+
+    /// Sets the configured fields in the builder
+    pub fn apply(&self, builder: &mut VersionBuilder) {
+        if let Some(protocol_version) = self.protocol_version {
+            builder.protocol_version(protocol_version);
+        }
+        if let Some(services) = self.services {
+            builder.services(services).sender_services(services);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder.user_agent(user_agent.clone());
+        }
+        if let Some(start_height) = self.start_height {
+            builder.start_height(start_height);
+        }
+        if let Some(relay) = self.relay {
+            builder.relay(relay);
+        }
+    }
+}
+
+/// What is announced to the peers during the handshake
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct HandshakeConfig {
+    /// Overrides of the top level version section
+    #[serde(default)]
+    pub version: VersionConfig,
+
+    /// Features announced to every peer
+    #[serde(default)]
+    pub features: FeaturesConfig,
+
+    /// Services the peers must offer, the ones missing any of them are dropped once the
+    /// handshake is complete
+    #[serde(default)]
+    pub required_services: ServiceFlags,
+}
+
+impl HandshakeConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        let version = &self.version;
+        let protocol_version = version.protocol_version.unwrap_or(PROTOCOL_VERSION);
+        if protocol_version < MIN_PEER_PROTOCOL_VERSION {
+            return Err(Error::ProtocolVersionTooLow(protocol_version));
+        }
+        if self.features.wtxid_relay && protocol_version < WTXID_RELAY_VERSION {
+            return Err(Error::FeatureNotSupported(
+                MessageType::WtxIdRelay.to_string(),
+                protocol_version,
+            ));
+        }
+        if let Some(user_agent) = &version.user_agent {
+            if user_agent.len() > MAX_USER_AGENT_LENGTH {
+                return Err(Error::UserAgentTooLong(user_agent.len()));
+            }
+        }
+        if let Some(start_height) = version.start_height {
+            if start_height < 0 {
+                return Err(Error::NegativeStartHeight(start_height));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PingConfig {
+    /// Seconds between pings
+    pub interval: u64,
+
+    /// Seconds to wait for the pong before disconnecting
+    pub timeout: u64,
+}
+
+impl PingConfig {
+    /// A zero interval would ping without pause and a zero timeout would never wait for a pong
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.interval == 0 {
+            return Err(Error::ZeroPingDuration("interval"));
+        }
+        if self.timeout == 0 {
+            return Err(Error::ZeroPingDuration("timeout"));
+        }
+        Ok(())
+    }
+}
+
+impl Default for PingConfig {
+    /// Same values as Bitcoin Core
+    fn default() -> Self {
+        Self {
+            interval: 120,
+            timeout: 1200,
+        }
+    }
+}
+
+/// Limits of the `crawl` mode
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CrawlerConfig {
+    /// Peers crawled at the same time
+    pub max_concurrency: usize,
+
+    /// Hops followed from the seed addresses, which are at depth 0
+    pub max_depth: usize,
+
+    /// Addresses learned once this many peers were queued are ignored
+    pub max_peers: usize,
+}
+
+impl Default for CrawlerConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 32,
+            max_depth: 2,
+            max_peers: 1000,
+        }
+    }
+}
+
+/// Optional features announced to the peers, addrv2 support is always announced
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FeaturesConfig {
+    /// Send wtxidrelay before the verack to relay transactions by wtxid
+    pub wtxid_relay: bool,
+
+    /// Send sendheaders after the verack to receive new blocks as headers
+    pub send_headers: bool,
+
+    /// Send sendcmpct version 2 after the verack, without asking for new blocks as compact blocks
+    pub compact_blocks: bool,
+
+    /// Send feefilter after the verack with this fee rate in satoshis per 1000 virtual bytes
+    pub fee_filter: Option<u64>,
+}
+
+impl FeaturesConfig {
+    /// Messages to send between version and verack
+    pub fn before_verack(&self) -> Vec<(Payload, MessageType)> {
+        let mut messages = vec![(Payload::SendAddrV2(SendAddrV2), MessageType::SendAddrV2)];
+        if self.wtxid_relay {
+            messages.push((Payload::WtxIdRelay(WtxIdRelay), MessageType::WtxIdRelay));
+        }
+        messages
+    }
+
+    /// Messages to send once the verack is received
+    pub fn after_verack(&self) -> Vec<(Payload, MessageType)> {
+        let mut messages = Vec::new();
+        if self.send_headers {
+            messages.push((Payload::SendHeaders(SendHeaders), MessageType::SendHeaders));
+        }
+        if self.compact_blocks {
+            messages.push((
+                Payload::SendCmpct(SendCmpct::new(false, 2)),
+                MessageType::SendCmpct,
+            ));
+        }
+        if let Some(fee_rate) = self.fee_filter {
+            messages.push((
+                Payload::FeeFilter(FeeFilter::new(fee_rate)),
+                MessageType::FeeFilter,
+            ));
+        }
+        messages
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct ListenerConfig {
-    /// Target TCP port
-    pub port: u16,
+    /// Target TCP port, defaults to the network's port
+    pub port: Option<u16>,
 
-    /// Network: mainnet or testnet
+    /// Network: mainnet, testnet, testnet4, signet, custom_signet or regtest
     pub network: Network,
+
+    /// Maximum payload lengths accepted from the peers
+    #[serde(flatten)]
+    pub limits: LimitsConfig,
+
+    /// Periodic pings sent to every connected peer, incoming pings are always answered
+    pub ping: Option<PingConfig>,
+
+    /// Version and features announced to every peer
+    #[serde(flatten)]
+    pub handshake: HandshakeConfig,
+}
+
+impl ListenerConfig {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or_else(|| self.network.default_port())
+    }
+}
+
+/// Maximum payload lengths accepted from the peers, shared by the listener and the sender
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct LimitsConfig {
+    /// Maximum payload length in bytes accepted from a peer, defaults to 4,000,000
+    pub max_payload_length: Option<u32>,
+
+    /// Maximum payload length in bytes per message type, overrides `max_payload_length`
+    #[serde(default)]
+    pub max_payload_length_per_type: HashMap<String, u32>,
+}
+
+impl LimitsConfig {
+    pub fn payload_limits(&self) -> Result<PayloadLimits, Error> {
+        let default = self
+            .max_payload_length
+            .unwrap_or(MAX_PROTOCOL_MESSAGE_LENGTH);
+
+        self.max_payload_length_per_type.iter().try_fold(
+            PayloadLimits::new(default),
+            |limits, (ty, limit)| match ty.parse::<MessageType>() {
+                Ok(MessageType::Unknown(_)) | Err(_) => Err(Error::UnknownMessageType(ty.clone())),
+                Ok(ty) => Ok(limits.with_limit(ty, *limit)),
+            },
+        )
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct SenderConfig {
-    /// Bitcoin DNS seed
-    pub dns_seed: String,
+    /// Bitcoin DNS seed, defaults to the network's DNS seeds
+    pub dns_seed: Option<String>,
 
-    /// Target TCP port
-    pub port: u16,
+    /// Target TCP port, defaults to the network's port
+    pub port: Option<u16>,
 
-    /// Network: mainnet or testnet
+    /// Network: mainnet, testnet, testnet4, signet, custom_signet or regtest
     pub network: Network,
+
+    /// Keep the connections alive after the handshake with periodic pings
+    pub ping: Option<PingConfig>,
+
+    /// Maximum payload lengths accepted from the peers, a block may need more than the default
+    #[serde(flatten)]
+    pub limits: LimitsConfig,
+
+    /// Request and log the addresses known by every peer after the handshake
+    #[serde(default)]
+    pub get_addr: bool,
+
+    /// File where the `sync-headers` mode stores the best header chain, defaults to
+    /// `headers_<network>.dat`
+    pub headers_file: Option<PathBuf>,
+
+    /// Outbound handshakes in progress at the same time, the other targets wait for a free
+    /// slot. Unlimited by default
+    pub max_concurrent_handshakes: Option<usize>,
+
+    /// New outbound connections opened per second, unlimited by default
+    pub connections_per_second: Option<u32>,
+
+    /// Limits of the `crawl` mode
+    #[serde(default)]
+    pub crawler: CrawlerConfig,
+
+    /// Version and features announced to every peer
+    #[serde(flatten)]
+    pub handshake: HandshakeConfig,
+}
+
+impl SenderConfig {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or_else(|| self.network.default_port())
+    }
+
+    pub fn dns_seeds(&self) -> Vec<String> {
+        match &self.dns_seed {
+            Some(dns_seed) => vec![dns_seed.clone()],
+            None => self
+                .network
+                .dns_seeds()
+                .iter()
+                .map(|dns_seed| dns_seed.to_string())
+                .collect(),
+        }
+    }
+
+    /// Limits shared by every outbound handshake, `resolve` already rejected the zero limits
+    pub fn limiter(&self) -> Limiter {
+        Limiter::new(
+            self.max_concurrent_handshakes.and_then(NonZeroUsize::new),
+            self.connections_per_second.and_then(NonZeroU32::new),
+        )
+    }
+
+    pub fn headers_file(&self) -> PathBuf {
+        self.headers_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("headers_{}.dat", self.network.name())))
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Failed to read the file {0}")]
     File(Box<Path>),
+    #[error("Unknown message type {0}")]
+    UnknownMessageType(String),
+    #[error("The ping {0} must be at least one second")]
+    ZeroPingDuration(&'static str),
+    #[error("Protocol version {0} below the minimum of {MIN_PEER_PROTOCOL_VERSION}")]
+    ProtocolVersionTooLow(i32),
+    #[error("{0} is not supported by protocol version {1}")]
+    FeatureNotSupported(String, i32),
+    #[error("User agent of {0} bytes, maximum is {MAX_USER_AGENT_LENGTH}")]
+    UserAgentTooLong(usize),
+    #[error("Negative start height {0}")]
+    NegativeStartHeight(i32),
+    #[error("At least one peer at a time must be allowed")]
+    NoConcurrency,
+    #[error("At least one connection per second must be allowed")]
+    NoConnectionRate,
 }
 
 #[derive(Parser)]
@@ -68,4 +433,199 @@ pub struct Args {
     /// Sets a custom configuration file
     #[clap(short, long, default_value = "config_files/testnet.yaml")]
     pub config: String,
+
+    /// What the sender does with the peers
+    #[clap(short, long, value_enum, default_value_t = Mode::Handshake)]
+    pub mode: Mode,
+
+    /// Hash of the block to download in the `get-block` mode
+    #[clap(long, required_if_eq("mode", "get-block"))]
+    pub block: Option<Hash256>,
+
+    /// File with one hex encoded transaction per line for the `broadcast` mode, `-` reads stdin
+    #[clap(long, required_if_eq("mode", "broadcast"))]
+    pub transactions: Option<PathBuf>,
+
+    /// Writes one record per target in the `handshake` and `crawl` modes once every handshake
+    /// finished
+    #[clap(long, value_enum)]
+    pub output: Option<Format>,
+
+    /// File the records are written to instead of stdout
+    #[clap(long, requires = "output")]
+    pub output_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    /// Perform the handshake with every peer
+    Handshake,
+    /// Download and validate the best header chain from the first peer that answers
+    SyncHeaders,
+    /// Download and validate a single block from the first peer that has it
+    GetBlock,
+    /// Announce transactions to every peer and report what each peer did with them
+    Broadcast,
+    /// Map the network by asking every peer for the addresses it knows, starting from the seeds
+    Crawl,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn resolve(yaml: &str) -> Result<Config, Error> {
+        let mut config = serde_yaml::from_str::<Config>(yaml).expect("valid yaml");
+        config.resolve()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn test_version_overrides() {
+        let config = resolve(
+            r#"
+version:
+  user_agent: "/bitcoin-p2p:0.1.0/"
+  start_height: 800000
+listener:
+  network: regtest
+  version:
+    services: NODE_NETWORK | NODE_WITNESS | 0x1000000
+    start_height: 0
+    relay: true
+sender:
+  network: regtest
+  required_services: NODE_WITNESS
+"#,
+        )
+        .expect("resolve");
+
+        // The listener overrides the start height and keeps the user agent
+        let listener = config.listener.expect("listener").handshake.version;
+        assert_eq!(listener.user_agent.as_deref(), Some("/bitcoin-p2p:0.1.0/"));
+        assert_eq!(listener.start_height, Some(0));
+        assert_eq!(listener.relay, Some(true));
+        assert_eq!(
+            listener.services.map(|services| services.bits()),
+            Some(1 << 24 | 0x09)
+        );
+
+        // The sender keeps the top level version
+        let sender = config.sender.expect("sender").handshake;
+        assert_eq!(sender.version, config.version);
+        assert_eq!(sender.required_services, ServiceFlags::NODE_WITNESS);
+
+        // The fields are applied to the version message
+        let mut builder = VersionBuilder::default();
+        builder
+            .receiver_address("127.0.0.1:18444".parse::<std::net::SocketAddr>().unwrap())
+            .sender_address("127.0.0.1:50000".parse::<std::net::SocketAddr>().unwrap());
+        listener.apply(&mut builder);
+        let version = builder.build().expect("version");
+        assert_eq!(version.user_agent(), "/bitcoin-p2p:0.1.0/");
+        assert_eq!(*version.protocol_version(), PROTOCOL_VERSION);
+        assert!(*version.relay());
+        assert_eq!(version.services(), version.sender_services());
+    }
+
+    #[test]
+    fn test_invalid_version() {
+        assert!(matches!(
+            resolve("version:\n  protocol_version: 209\nsender:\n  network: regtest"),
+            Err(Error::ProtocolVersionTooLow(209))
+        ));
+        assert!(matches!(
+            resolve(
+                "sender:\n  network: regtest\n  version:\n    protocol_version: 70015\n  features:\n    wtxid_relay: true"
+            ),
+            Err(Error::FeatureNotSupported(_, 70015))
+        ));
+        let user_agent = "a".repeat(257);
+        assert!(matches!(
+            resolve(&format!(
+                "listener:\n  network: regtest\n  version:\n    user_agent: {user_agent}"
+            )),
+            Err(Error::UserAgentTooLong(257))
+        ));
+        assert!(matches!(
+            resolve("version:\n  start_height: -1\nlistener:\n  network: regtest"),
+            Err(Error::NegativeStartHeight(-1))
+        ));
+    }
+
+    #[test]
+    fn test_crawler_config() {
+        let config = resolve("sender:\n  network: regtest\n  crawler:\n    max_depth: 5")
+            .expect("valid config");
+        let crawler = config.sender.expect("sender").crawler;
+
+        // Assert that the fields not set keep the defaults
+        assert_eq!(crawler.max_depth, 5);
+        assert_eq!(crawler.max_concurrency, 32);
+        assert_eq!(crawler.max_peers, 1000);
+
+        assert!(matches!(
+            resolve("sender:\n  network: regtest\n  crawler:\n    max_concurrency: 0"),
+            Err(Error::NoConcurrency)
+        ));
+    }
+
+    #[test]
+    fn test_connection_limits() {
+        let config = resolve(
+            "sender:\n  network: regtest\n  max_concurrent_handshakes: 8\n  connections_per_second: 20",
+        )
+        .expect("valid config");
+        let sender = config.sender.expect("sender");
+        assert_eq!(sender.max_concurrent_handshakes, Some(8));
+        assert_eq!(sender.connections_per_second, Some(20));
+
+        assert!(matches!(
+            resolve("sender:\n  network: regtest\n  max_concurrent_handshakes: 0"),
+            Err(Error::NoConcurrency)
+        ));
+        assert!(matches!(
+            resolve("sender:\n  network: regtest\n  connections_per_second: 0"),
+            Err(Error::NoConnectionRate)
+        ));
+    }
+
+    #[test]
+    fn test_sender_payload_limits() {
+        let config = resolve(
+            "sender:\n  network: regtest\n  max_payload_length_per_type:\n    block: 8000000",
+        )
+        .expect("valid config");
+        let limits = config
+            .sender
+            .expect("sender")
+            .limits
+            .payload_limits()
+            .expect("limits");
+
+        // Assert that a limit above the default one is kept
+        assert_eq!(limits.limit(&MessageType::Block), 8_000_000);
+        assert_eq!(limits.limit(&MessageType::Tx), MAX_PROTOCOL_MESSAGE_LENGTH);
+
+        assert!(matches!(
+            resolve("sender:\n  network: regtest\n  max_payload_length_per_type:\n    blocks: 1"),
+            Err(Error::UnknownMessageType(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_ping() {
+        assert!(matches!(
+            resolve("listener:\n  network: regtest\n  ping:\n    interval: 0\n    timeout: 20"),
+            Err(Error::ZeroPingDuration("interval"))
+        ));
+        assert!(matches!(
+            resolve("sender:\n  network: regtest\n  ping:\n    interval: 10\n    timeout: 0"),
+            Err(Error::ZeroPingDuration("timeout"))
+        ));
+        assert!(
+            resolve("sender:\n  network: regtest\n  ping:\n    interval: 10\n    timeout: 20")
+                .is_ok()
+        );
+    }
 }
@@ -0,0 +1,263 @@
+use crate::config::{CrawlerConfig, HandshakeConfig};
+use crate::report::Record;
+use crate::sender::{self, Limiter};
+use bitcoin::codec::PayloadLimits;
+use bitcoin::network::Network;
+use dashmap::DashSet;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{error, info};
+
+/// Crawls the network from the seed addresses.
+///
+/// Every peer is asked for the addresses it knows after the handshake, the ones not seen before
+/// are queued one hop further until `max_depth` is reached. At most `max_concurrency` peers are
+/// crawled at the same time, the handshakes also wait for the `limiter`, and every queued address
+/// gets a record whether it was reachable or not.
+pub async fn run(
+    seeds: Vec<SocketAddr>,
+    network: Arc<Network>,
+    limits: PayloadLimits,
+    handshake_config: Arc<HandshakeConfig>,
+    nonces: Arc<DashSet<u64>>,
+    limiter: Limiter,
+    config: &CrawlerConfig,
+) -> Vec<Record> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    for seed in seeds {
+        if seen.len() < config.max_peers && seen.insert(seed) {
+            queue.push_back((seed, 0));
+        }
+    }
+
+    let mut records = Vec::new();
+    let mut tasks = JoinSet::new();
+    loop {
+        while tasks.len() < config.max_concurrency {
+            let Some((addr, depth)) = queue.pop_front() else {
+                break;
+            };
+            tasks.spawn(visit(
+                addr,
+                depth,
+                network.clone(),
+                limits.clone(),
+                handshake_config.clone(),
+                nonces.clone(),
+                limiter.clone(),
+            ));
+        }
+
+        let Some(result) = tasks.join_next().await else {
+            break;
+        };
+        match result {
+            Ok((record, depth, learned)) => {
+                if depth < config.max_depth {
+                    for addr in learned {
+                        if seen.len() < config.max_peers && seen.insert(addr) {
+                            queue.push_back((addr, depth + 1));
+                        }
+                    }
+                }
+                records.push(record);
+            }
+            Err(e) => error!("{e:?}"),
+        }
+    }
+
+    records
+}
+
+/// Performs the handshake and asks for the addresses the peer knows
+async fn visit(
+    addr: SocketAddr,
+    depth: usize,
+    network: Arc<Network>,
+    limits: PayloadLimits,
+    handshake_config: Arc<HandshakeConfig>,
+    nonces: Arc<DashSet<u64>>,
+    limiter: Limiter,
+) -> (Record, usize, Vec<SocketAddr>) {
+    let permit = limiter.acquire().await;
+    let start = Instant::now();
+    let result = sender::run(&addr, network.clone(), limits, handshake_config, nonces).await;
+    drop(permit);
+    let (mut info, mut framed) = match result {
+        Ok(connection) => connection,
+        Err(e) => {
+            error!("{e:?}");
+            return (Record::failure(addr, &e, start.elapsed()), depth, vec![]);
+        }
+    };
+    let record = Record::success(&info, start.elapsed());
+
+    let received = info.take_received();
+    let learned: Vec<SocketAddr> =
+        match sender::get_addr(&mut framed, &addr, &network, received).await {
+            // Onion and I2P addresses can not be reached over TCP
+            Ok(addresses) => addresses.iter().filter_map(|a| a.socket_addr()).collect(),
+            Err(e) => {
+                error!("{e:?}");
+                vec![]
+            }
+        };
+    info!(
+        "{addr} at depth {depth} runs {} at height {} ({}), sent {} addresses",
+        info.user_agent(),
+        info.start_height(),
+        info.services(),
+        learned.len()
+    );
+
+    (record, depth, learned)
+}
+
+/// Logs how many peers were reachable and which user agents they run
+pub fn log_inventory(records: &[Record]) {
+    let reachable = records.iter().filter(|record| record.success).count();
+    info!(
+        "Crawled {} peers, {reachable} reachable and {} unreachable",
+        records.len(),
+        records.len() - reachable
+    );
+
+    let mut user_agents = BTreeMap::new();
+    for user_agent in records
+        .iter()
+        .filter_map(|record| record.user_agent.as_ref())
+    {
+        *user_agents.entry(user_agent).or_insert(0) += 1;
+    }
+    for (user_agent, count) in user_agents {
+        info!("{count} peers run {user_agent}");
+    }
+    if let Some(height) = records
+        .iter()
+        .filter_map(|record| record.start_height)
+        .max()
+    {
+        info!("Highest start height announced: {height}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_peer;
+    use bitcoin::addr::{Addr, NetAddr};
+    use bitcoin::message_type::MessageType;
+    use bitcoin::services::ServiceFlags;
+    use bitcoin::Payload;
+
+    /// Spawns stand-in peers on loopback, each one answering getaddr with the peers at the
+    /// indexes in `known`. Returns their addresses and one address nobody listens on.
+    async fn spawn_fleet(known: &[&[usize]]) -> (Vec<SocketAddr>, SocketAddr) {
+        let mut listeners = Vec::new();
+        for _ in known {
+            listeners.push(test_peer::bind().await);
+        }
+        let (unreachable, unreachable_addr) = test_peer::bind().await;
+        drop(unreachable);
+
+        let addresses: Vec<SocketAddr> = listeners.iter().map(|(_, addr)| *addr).collect();
+        for ((listener, _), known) in listeners.into_iter().zip(known) {
+            let known: Vec<NetAddr> = known
+                .iter()
+                .map(|&index| addresses.get(index).copied().unwrap_or(unreachable_addr))
+                .map(|addr| NetAddr::new(0, ServiceFlags::NODE_NETWORK, addr))
+                .collect();
+            tokio::spawn(async move {
+                let network = Network::Regtest;
+                loop {
+                    let mut framed = test_peer::accept(&listener, &network).await;
+                    while !matches!(
+                        test_peer::receive(&mut framed).await.payload(),
+                        Payload::GetAddr(_)
+                    ) {}
+                    test_peer::send(
+                        &mut framed,
+                        Payload::Addr(Addr::new(known.clone())),
+                        MessageType::Addr,
+                        &network,
+                    )
+                    .await;
+                }
+            });
+        }
+
+        (addresses, unreachable_addr)
+    }
+
+    async fn crawl(seed: SocketAddr, max_depth: usize) -> Vec<Record> {
+        let config = CrawlerConfig {
+            max_concurrency: 2,
+            max_depth,
+            ..CrawlerConfig::default()
+        };
+        let mut records = run(
+            vec![seed],
+            Arc::new(Network::Regtest),
+            PayloadLimits::default(),
+            Arc::default(),
+            Arc::default(),
+            Limiter::default(),
+            &config,
+        )
+        .await;
+        records.sort_by_key(|record| record.address);
+        records
+    }
+
+    #[tokio::test]
+    async fn test_crawl() {
+        // Index 4 is the address nobody listens on
+        let (fleet, unreachable) = spawn_fleet(&[&[1, 2], &[0, 3], &[4, 0], &[0, 1]]).await;
+
+        let records = crawl(fleet[0], 3).await;
+
+        // Assert that every peer was crawled once and the unreachable one was reported
+        let mut expected = fleet.clone();
+        expected.push(unreachable);
+        expected.sort();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.address)
+                .collect::<Vec<_>>(),
+            expected
+        );
+        for record in &records {
+            if record.address == unreachable {
+                assert!(!record.success);
+                assert_eq!(record.error, Some("tcp_connection"));
+            } else {
+                assert!(record.success);
+                assert_eq!(record.user_agent.as_deref(), Some("Satoshi:0.21.0"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_crawl_max_depth() {
+        let (fleet, _) = spawn_fleet(&[&[1, 2], &[0, 3], &[4, 0], &[0, 1]]).await;
+
+        let records = crawl(fleet[0], 1).await;
+
+        // Assert that only the seed and the peers it knows were crawled
+        let mut expected = vec![fleet[0], fleet[1], fleet[2]];
+        expected.sort();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.address)
+                .collect::<Vec<_>>(),
+            expected
+        );
+        assert!(records.iter().all(|record| record.success));
+    }
+}
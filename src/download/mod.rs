@@ -0,0 +1,95 @@
+use bitcoin::block::Block;
+use bitcoin::codec::MessageCodec;
+use bitcoin::hash::Hash256;
+use bitcoin::inventory::{GetData, InvType, InvVector};
+use bitcoin::message_type::MessageType;
+use bitcoin::network::Network;
+use bitcoin::ping::Pong;
+use bitcoin::{Message, Payload, SerdeBitcoinError};
+use futures::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tokio::time::timeout;
+use tokio_util::codec::Framed;
+
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Requests the block with its witness data and validates it against its header
+pub async fn block(
+    framed: &mut Framed<TcpStream, MessageCodec>,
+    addr: &SocketAddr,
+    network: &Network,
+    hash: &Hash256,
+) -> Result<Block, Error> {
+    let get_data = GetData::new(vec![InvVector::new(InvType::WitnessBlock, *hash)]);
+    let message = Message::build(Payload::GetData(get_data), MessageType::GetData, network);
+    framed.send(message).await.map_err(Error::SendGetData)?;
+
+    let block = timeout(BLOCK_TIMEOUT, receive_block(framed, addr, network, hash))
+        .await
+        .map_err(Error::BlockTimeout)??;
+    block
+        .validate(network)
+        .map_err(|e| Error::InvalidBlock(*hash, e))?;
+
+    Ok(block)
+}
+
+async fn receive_block(
+    framed: &mut Framed<TcpStream, MessageCodec>,
+    addr: &SocketAddr,
+    network: &Network,
+    hash: &Hash256,
+) -> Result<Block, Error> {
+    loop {
+        let message = framed
+            .next()
+            .await
+            .ok_or(Error::ConnectionClosed)?
+            .map_err(Error::Deserialize)?;
+        match message.payload() {
+            Payload::Block(block) if block.block_hash() == *hash => return Ok(block.clone()),
+            Payload::NotFound(not_found)
+                if not_found
+                    .inventory()
+                    .iter()
+                    .any(|entry| entry.hash() == hash) =>
+            {
+                return Err(Error::BlockNotFound(addr.to_string(), *hash));
+            }
+            Payload::Ping(ping) => {
+                let pong = Pong::new(*ping.nonce());
+                framed
+                    .send(Message::build(
+                        Payload::Pong(pong),
+                        MessageType::Pong,
+                        network,
+                    ))
+                    .await
+                    .map_err(Error::SendPong)?;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to send the getdata message")]
+    SendGetData(#[source] SerdeBitcoinError),
+    #[error("Failed to send the pong message")]
+    SendPong(#[source] SerdeBitcoinError),
+    #[error("Failed to deserialize the message")]
+    Deserialize(#[source] SerdeBitcoinError),
+    #[error("Connection closed by the peer")]
+    ConnectionClosed,
+    #[error("Block timeout")]
+    BlockTimeout(#[source] Elapsed),
+    #[error("{0} does not have the block {1}")]
+    BlockNotFound(String, Hash256),
+    #[error("Block {0} is invalid")]
+    InvalidBlock(Hash256, #[source] SerdeBitcoinError),
+}
@@ -0,0 +1,716 @@
+use crate::config::HandshakeConfig;
+use bitcoin::codec::MessageCodec;
+use bitcoin::feature::{self, Features};
+use bitcoin::message_type::MessageType;
+use bitcoin::network::Network;
+use bitcoin::ping::{Ping, Pong};
+use bitcoin::reject::Reject;
+use bitcoin::services::ServiceFlags;
+use bitcoin::verack::VerAck;
+use bitcoin::version::{Version, VersionBuilder, VersionBuilderError};
+use bitcoin::{Message, Payload, SerdeBitcoinError};
+use dashmap::DashSet;
+use futures::{SinkExt, StreamExt};
+use getset::Getters;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::error::Elapsed;
+use tokio::time::{timeout_at, Instant};
+use tokio_util::codec::Framed;
+use tracing::{error, info};
+
+const VERSION_TIMEOUT: Duration = Duration::from_secs(30);
+const VERACK_TIMEOUT: Duration = Duration::from_secs(30);
+const FEATURES_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Who opened the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// We connected to the peer, our version goes first
+    Outbound,
+    /// The peer connected to us, our version answers theirs
+    Inbound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Waiting for the version of the peer
+    AwaitingVersion,
+    /// Waiting for the verack of the peer, it may negotiate features before it
+    AwaitingVerack,
+    /// Collecting the features sent right after the verack until the pong of our ping arrives,
+    /// only outbound handshakes wait for it
+    AwaitingPong,
+    /// The handshake is complete
+    Done,
+}
+
+/// Handshake state machine without any IO.
+///
+/// Received messages are fed with `receive`, which returns the new state on every transition,
+/// and the messages to send are taken in order with `next_outgoing`.
+#[derive(Getters)]
+pub struct Handshake {
+    #[getset(get = "pub")]
+    direction: Direction,
+
+    #[getset(get = "pub")]
+    state: State,
+
+    #[getset(get = "pub")]
+    addr: SocketAddr,
+
+    local_addr: SocketAddr,
+    network: Arc<Network>,
+    config: Arc<HandshakeConfig>,
+
+    /// Nonces of the versions sent by the outbound handshakes in progress
+    nonces: Arc<DashSet<u64>>,
+
+    /// Nonce of our version
+    nonce: Option<u64>,
+
+    /// Version sent by the peer
+    #[getset(get = "pub")]
+    peer_version: Option<Version>,
+
+    /// Features negotiated by the peer
+    #[getset(get = "pub")]
+    features: Features,
+
+    /// Commands not part of `MessageType` received during the handshake
+    #[getset(get = "pub")]
+    unknown_commands: HashMap<String, u64>,
+
+    /// Nonce of the ping sent after the verack
+    ping_nonce: Option<u64>,
+    outgoing: VecDeque<Message>,
+
+    /// Messages that are not part of the handshake, received while waiting for the pong
+    received: Vec<Message>,
+}
+
+impl Handshake {
+    /// Starts the handshake with a peer we connected to, our version is queued right away and
+    /// its nonce is kept in `nonces` until the handshake is dropped
+    pub fn outbound(
+        addr: SocketAddr,
+        local_addr: SocketAddr,
+        network: Arc<Network>,
+        config: Arc<HandshakeConfig>,
+        nonces: Arc<DashSet<u64>>,
+    ) -> Result<Self, Error> {
+        let mut handshake = Self::new(
+            Direction::Outbound,
+            addr,
+            local_addr,
+            network,
+            config,
+            nonces,
+        );
+        handshake.queue_version()?;
+        Ok(handshake)
+    }
+
+    /// Waits for the handshake of a peer that connected to us, peers presenting one of the
+    /// `nonces` are ourselves
+    pub fn inbound(
+        addr: SocketAddr,
+        local_addr: SocketAddr,
+        network: Arc<Network>,
+        config: Arc<HandshakeConfig>,
+        nonces: Arc<DashSet<u64>>,
+    ) -> Self {
+        Self::new(
+            Direction::Inbound,
+            addr,
+            local_addr,
+            network,
+            config,
+            nonces,
+        )
+    }
+
+    fn new(
+        direction: Direction,
+        addr: SocketAddr,
+        local_addr: SocketAddr,
+        network: Arc<Network>,
+        config: Arc<HandshakeConfig>,
+        nonces: Arc<DashSet<u64>>,
+    ) -> Self {
+        Self {
+            direction,
+            state: State::AwaitingVersion,
+            addr,
+            local_addr,
+            network,
+            config,
+            nonces,
+            nonce: None,
+            peer_version: None,
+            features: Features::default(),
+            unknown_commands: HashMap::new(),
+            ping_nonce: None,
+            outgoing: VecDeque::new(),
+            received: Vec::new(),
+        }
+    }
+
+    /// Next message to send to the peer
+    pub fn next_outgoing(&mut self) -> Option<Message> {
+        self.outgoing.pop_front()
+    }
+
+    /// Takes the messages received while waiting for the pong that are not part of the
+    /// handshake, in order. Peers may announce their address or relay inventory meanwhile.
+    pub fn take_received(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.received)
+    }
+
+    /// Processes a message received from the peer, returns the new state if it changed
+    pub fn receive(&mut self, message: Message) -> Result<Option<State>, Error> {
+        if let MessageType::Unknown(command) = message.ty() {
+            count_unknown_command(&mut self.unknown_commands, &self.addr, command);
+            return Ok(None);
+        }
+
+        match self.state {
+            State::AwaitingVersion => {
+                let Payload::Version(version) = message.payload() else {
+                    return Err(self.unexpected(&message, MessageType::Version));
+                };
+                if self.direction == Direction::Inbound && self.nonces.contains(version.nonce()) {
+                    error!(
+                        "{} sent the nonce of our own version, disconnecting",
+                        self.addr
+                    );
+                    return Err(Error::SelfConnection(self.addr.to_string()));
+                }
+                self.peer_version = Some(version.clone());
+                if self.direction == Direction::Inbound {
+                    self.queue_version()?;
+                }
+
+                // Announce our features, they must be sent before the verack. Our verack does not
+                // wait for the peer's one, like Bitcoin Core
+                self.queue_all(self.config.features.before_verack());
+                self.queue(Payload::VerAck(VerAck), MessageType::VerAck);
+                Ok(self.transition(State::AwaitingVerack))
+            }
+            // The peer may negotiate features before its verack
+            State::AwaitingVerack if self.features.record(message.payload()) => Ok(None),
+            State::AwaitingVerack => {
+                if *message.ty() != MessageType::VerAck {
+                    return Err(self.unexpected(&message, MessageType::VerAck));
+                }
+
+                match self.direction {
+                    Direction::Outbound => {
+                        self.queue_all(self.config.features.after_verack());
+                        let ping = Ping::new();
+                        self.ping_nonce = Some(*ping.nonce());
+                        self.queue(Payload::Ping(ping), MessageType::Ping);
+                        Ok(self.transition(State::AwaitingPong))
+                    }
+                    Direction::Inbound => {
+                        self.queue_all(self.config.features.after_verack());
+                        self.complete()
+                    }
+                }
+            }
+            State::AwaitingPong => {
+                if feature::is_negotiated_before_verack(message.ty()) {
+                    return Err(Error::FeatureAfterVerack(message.ty().to_string()));
+                }
+                match message.payload() {
+                    Payload::Pong(pong) if Some(*pong.nonce()) == self.ping_nonce => {
+                        self.complete()
+                    }
+                    Payload::Ping(ping) => {
+                        self.queue(Payload::Pong(Pong::new(*ping.nonce())), MessageType::Pong);
+                        Ok(None)
+                    }
+                    // Features are recorded, anything else is kept for the caller
+                    payload => {
+                        if !self.features.record(payload) {
+                            self.received.push(message);
+                        }
+                        Ok(None)
+                    }
+                }
+            }
+            State::Done => Ok(None),
+        }
+    }
+
+    /// The pong did not arrive in time, the handshake is complete with the features received
+    /// so far
+    pub fn pong_timeout(&mut self) -> Result<Option<State>, Error> {
+        if self.state != State::AwaitingPong {
+            return Ok(None);
+        }
+        self.complete()
+    }
+
+    /// Completes the handshake if the peer offers the required services
+    fn complete(&mut self) -> Result<Option<State>, Error> {
+        let services = self
+            .peer_version
+            .as_ref()
+            .map(|version| *version.services())
+            .unwrap_or_default();
+        let missing = self.config.required_services.difference(services);
+        if !missing.is_empty() {
+            info!(
+                "{} does not offer the services {missing}, disconnecting",
+                self.addr
+            );
+            return Err(Error::MissingServices(self.addr.to_string(), missing));
+        }
+        Ok(self.transition(State::Done))
+    }
+
+    fn transition(&mut self, state: State) -> Option<State> {
+        self.state = state;
+        Some(state)
+    }
+
+    fn unexpected(&self, message: &Message, expected: MessageType) -> Error {
+        match message.payload() {
+            Payload::Reject(reject) => Error::PeerRejected(self.addr.to_string(), reject.clone()),
+            _ => Error::ReceivedWrongMessageType(message.ty().to_string(), expected.to_string()),
+        }
+    }
+
+    fn queue_version(&mut self) -> Result<(), Error> {
+        let mut builder = VersionBuilder::default();
+        builder
+            .receiver_address(self.addr)
+            .sender_address(self.local_addr);
+        self.config.version.apply(&mut builder);
+        let version = builder.build().map_err(Error::BuildVersionPayload)?;
+        if self.direction == Direction::Outbound {
+            self.nonces.insert(*version.nonce());
+        }
+        self.nonce = Some(*version.nonce());
+        self.queue(Payload::Version(version), MessageType::Version);
+        Ok(())
+    }
+
+    fn queue_all(&mut self, messages: Vec<(Payload, MessageType)>) {
+        for (payload, ty) in messages {
+            self.queue(payload, ty);
+        }
+    }
+
+    fn queue(&mut self, payload: Payload, ty: MessageType) {
+        self.outgoing
+            .push_back(Message::build(payload, ty, &self.network));
+    }
+}
+
+impl Drop for Handshake {
+    fn drop(&mut self) {
+        if let (Direction::Outbound, Some(nonce)) = (self.direction, self.nonce) {
+            self.nonces.remove(&nonce);
+        }
+    }
+}
+
+/// Counts a command received from the peer that is not part of `MessageType`
+pub fn count_unknown_command(
+    unknown_commands: &mut HashMap<String, u64>,
+    addr: &SocketAddr,
+    command: &str,
+) {
+    let count = unknown_commands.entry(command.to_string()).or_default();
+    if *count == 0 {
+        info!("{addr} sent the unknown command {command}");
+    }
+    *count += 1;
+}
+
+/// Measured while driving the handshake
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timings {
+    /// Clock of the peer minus ours in seconds, taken when its version arrives
+    pub clock_offset: i64,
+
+    /// From sending our version until the verack of the peer arrives
+    pub latency: Duration,
+}
+
+/// Drives the handshake over the connection until it is done
+pub async fn run<S: AsyncRead + AsyncWrite + Unpin>(
+    framed: &mut Framed<S, MessageCodec>,
+    handshake: &mut Handshake,
+) -> Result<Timings, Error> {
+    let mut timings = Timings::default();
+    let mut version_sent = Instant::now();
+    let mut deadline = Instant::now() + timeout(handshake.state());
+    loop {
+        while let Some(message) = handshake.next_outgoing() {
+            let command = message.ty().to_string();
+            let is_version = *message.ty() == MessageType::Version;
+            framed
+                .send(message)
+                .await
+                .map_err(|e| Error::Send(command, e))?;
+            if is_version {
+                version_sent = Instant::now();
+            }
+        }
+        if *handshake.state() == State::Done {
+            return Ok(timings);
+        }
+
+        let message = match timeout_at(deadline, framed.next()).await {
+            Ok(message) => message.ok_or(Error::ConnectionClosed)?,
+            Err(e) => match handshake.state() {
+                State::AwaitingVersion => return Err(Error::VersionTimeout(e)),
+                State::AwaitingVerack => return Err(Error::VerackTimeout(e)),
+                // Keep the features received so far
+                State::AwaitingPong | State::Done => {
+                    handshake.pong_timeout()?;
+                    continue;
+                }
+            },
+        };
+        let message = match message {
+            Ok(message) => message,
+            Err(e @ SerdeBitcoinError::InvalidMagicBytes(..)) => {
+                error!(
+                    "{} sent a message for another network, disconnecting",
+                    handshake.addr()
+                );
+                return Err(Error::WrongNetwork(e));
+            }
+            Err(e) => return Err(Error::Deserialize(e)),
+        };
+
+        let previous = *handshake.state();
+        if let Some(state) = handshake.receive(message)? {
+            match (previous, state) {
+                (State::AwaitingVersion, _) => {
+                    if let Some(version) = handshake.peer_version() {
+                        timings.clock_offset = version.timestamp() - unix_time();
+                    }
+                }
+                (State::AwaitingVerack, _) => timings.latency = version_sent.elapsed(),
+                _ => {}
+            }
+            deadline = Instant::now() + timeout(&state);
+        }
+    }
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+/// Time given to the peer to move the handshake out of the state
+fn timeout(state: &State) -> Duration {
+    match state {
+        State::AwaitingVersion => VERSION_TIMEOUT,
+        State::AwaitingVerack => VERACK_TIMEOUT,
+        State::AwaitingPong => FEATURES_TIMEOUT,
+        State::Done => Duration::ZERO,
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to build the version payload")]
+    BuildVersionPayload(#[source] VersionBuilderError),
+    #[error("Failed to send the {0} message")]
+    Send(String, #[source] SerdeBitcoinError),
+    #[error("Failed to deserialize the message received during the handshake")]
+    Deserialize(#[source] SerdeBitcoinError),
+    #[error("Connection closed by the peer")]
+    ConnectionClosed,
+    #[error("Version timeout")]
+    VersionTimeout(#[source] Elapsed),
+    #[error("Verack timeout")]
+    VerackTimeout(#[source] Elapsed),
+    #[error("Peer is on a different network")]
+    WrongNetwork(#[source] SerdeBitcoinError),
+    #[error("Received wrong message type. Expected {1}, received {0}")]
+    ReceivedWrongMessageType(String, String),
+    #[error("{0} rejected the handshake: {1}")]
+    PeerRejected(String, Reject),
+    #[error("Received {0} after the verack, it must be sent before")]
+    FeatureAfterVerack(String),
+    #[error("Connected to ourselves through {0}")]
+    SelfConnection(String),
+    #[error("{0} does not offer the required services {1}")]
+    MissingServices(String, ServiceFlags),
+}
+
+impl Error {
+    /// Short name of the failure that stays the same across runs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::BuildVersionPayload(_) => "build_version_payload",
+            Error::Send(..) => "send",
+            Error::Deserialize(_) => "deserialize",
+            Error::ConnectionClosed => "connection_closed",
+            Error::VersionTimeout(_) => "version_timeout",
+            Error::VerackTimeout(_) => "verack_timeout",
+            Error::WrongNetwork(_) => "wrong_network",
+            Error::ReceivedWrongMessageType(..) => "wrong_message_type",
+            Error::PeerRejected(..) => "peer_rejected",
+            Error::FeatureAfterVerack(_) => "feature_after_verack",
+            Error::SelfConnection(_) => "self_connection",
+            Error::MissingServices(..) => "missing_services",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::addr::{Addr, NetAddr, SendAddrV2};
+    use bitcoin::feature::SendHeaders;
+    use bitcoin::inventory::Inv;
+    use bitcoin::reject::RejectCode;
+
+    fn handshake(direction: Direction) -> Handshake {
+        let addr = "127.0.0.1:18444".parse().unwrap();
+        let local_addr = "127.0.0.1:50000".parse().unwrap();
+        let network = Arc::new(Network::Regtest);
+        match direction {
+            Direction::Outbound => {
+                Handshake::outbound(addr, local_addr, network, Arc::default(), Arc::default())
+                    .expect("outbound")
+            }
+            Direction::Inbound => {
+                Handshake::inbound(addr, local_addr, network, Arc::default(), Arc::default())
+            }
+        }
+    }
+
+    fn message(payload: Payload, ty: MessageType) -> Message {
+        Message::build(payload, ty, &Network::Regtest)
+    }
+
+    fn version() -> Message {
+        let version = VersionBuilder::default()
+            .receiver_address("127.0.0.1:50000".parse::<SocketAddr>().unwrap())
+            .sender_address("127.0.0.1:18444".parse::<SocketAddr>().unwrap())
+            .build()
+            .unwrap();
+        message(Payload::Version(version), MessageType::Version)
+    }
+
+    fn verack() -> Message {
+        message(Payload::VerAck(VerAck), MessageType::VerAck)
+    }
+
+    fn outgoing(handshake: &mut Handshake) -> Vec<Message> {
+        std::iter::from_fn(|| handshake.next_outgoing()).collect()
+    }
+
+    fn types(messages: &[Message]) -> Vec<MessageType> {
+        messages
+            .iter()
+            .map(|message| message.ty().clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_outbound() {
+        let mut handshake = handshake(Direction::Outbound);
+        assert_eq!(types(&outgoing(&mut handshake)), vec![MessageType::Version]);
+
+        // The peer version is answered with our features and the verack
+        assert_eq!(
+            handshake.receive(version()).expect("version"),
+            Some(State::AwaitingVerack)
+        );
+        assert!(handshake.peer_version().is_some());
+        assert_eq!(
+            types(&outgoing(&mut handshake)),
+            vec![MessageType::SendAddrV2, MessageType::VerAck]
+        );
+
+        // Features and unknown commands are recorded before the verack
+        let send_addr_v2 = message(Payload::SendAddrV2(SendAddrV2), MessageType::SendAddrV2);
+        assert_eq!(handshake.receive(send_addr_v2).expect("sendaddrv2"), None);
+        let unknown = message(
+            Payload::Raw(Vec::new()),
+            MessageType::Unknown("sendpackages".to_string()),
+        );
+        assert_eq!(handshake.receive(unknown).expect("unknown"), None);
+
+        // The verack is followed by a ping to collect the late features
+        assert_eq!(
+            handshake.receive(verack()).expect("verack"),
+            Some(State::AwaitingPong)
+        );
+        let sent = outgoing(&mut handshake);
+        let Payload::Ping(ping) = sent[0].payload() else {
+            panic!("expected a ping");
+        };
+        let nonce = *ping.nonce();
+
+        let send_headers = message(Payload::SendHeaders(SendHeaders), MessageType::SendHeaders);
+        assert_eq!(handshake.receive(send_headers).expect("sendheaders"), None);
+
+        // The peer announces its address and relays inventory before answering
+        let self_announcement = message(
+            Payload::Addr(Addr::new(vec![NetAddr::new(
+                0,
+                ServiceFlags::NODE_NETWORK,
+                "127.0.0.1:18444".parse().unwrap(),
+            )])),
+            MessageType::Addr,
+        );
+        assert_eq!(handshake.receive(self_announcement).expect("addr"), None);
+        let inv = message(Payload::Inv(Inv::new(Vec::new())), MessageType::Inv);
+        assert_eq!(handshake.receive(inv).expect("inv"), None);
+
+        // Pings of the peer are answered meanwhile
+        let peer_ping = Ping::new();
+        let peer_nonce = *peer_ping.nonce();
+        let peer_ping = message(Payload::Ping(peer_ping), MessageType::Ping);
+        assert_eq!(handshake.receive(peer_ping).expect("ping"), None);
+        assert_eq!(
+            outgoing(&mut handshake)[0].payload(),
+            &Payload::Pong(Pong::new(peer_nonce))
+        );
+
+        let pong = message(Payload::Pong(Pong::new(nonce)), MessageType::Pong);
+        assert_eq!(handshake.receive(pong).expect("pong"), Some(State::Done));
+
+        assert!(handshake.features().addr_v2());
+        assert!(handshake.features().send_headers());
+        assert_eq!(handshake.unknown_commands().get("sendpackages"), Some(&1));
+
+        // Assert that the messages the handshake did not need are kept in order
+        assert_eq!(
+            types(&handshake.take_received()),
+            vec![MessageType::Addr, MessageType::Inv]
+        );
+        assert!(handshake.take_received().is_empty());
+    }
+
+    #[test]
+    fn test_inbound() {
+        let mut handshake = handshake(Direction::Inbound);
+        assert!(outgoing(&mut handshake).is_empty());
+
+        // Our version and verack answer the peer version
+        assert_eq!(
+            handshake.receive(version()).expect("version"),
+            Some(State::AwaitingVerack)
+        );
+        assert_eq!(
+            types(&outgoing(&mut handshake)),
+            vec![
+                MessageType::Version,
+                MessageType::SendAddrV2,
+                MessageType::VerAck
+            ]
+        );
+
+        // The peer verack completes the handshake
+        assert_eq!(
+            handshake.receive(verack()).expect("verack"),
+            Some(State::Done)
+        );
+        assert!(outgoing(&mut handshake).is_empty());
+    }
+
+    #[test]
+    fn test_wrong_order() {
+        // A verack before the version
+        let mut handshake = handshake(Direction::Inbound);
+        assert!(matches!(
+            handshake.receive(verack()),
+            Err(Error::ReceivedWrongMessageType(..))
+        ));
+
+        // A reject instead of the version
+        let mut handshake = self::handshake(Direction::Outbound);
+        let reject = Reject::new(
+            "version".to_string(),
+            RejectCode::Obsolete,
+            "obsolete".to_string(),
+            None,
+        );
+        assert!(matches!(
+            handshake.receive(message(Payload::Reject(reject), MessageType::Reject)),
+            Err(Error::PeerRejected(..))
+        ));
+
+        // A feature that must be sent before the verack after it
+        let mut handshake = self::handshake(Direction::Outbound);
+        handshake.receive(version()).expect("version");
+        handshake.receive(verack()).expect("verack");
+        let send_addr_v2 = message(Payload::SendAddrV2(SendAddrV2), MessageType::SendAddrV2);
+        assert!(matches!(
+            handshake.receive(send_addr_v2),
+            Err(Error::FeatureAfterVerack(_))
+        ));
+    }
+
+    #[test]
+    fn test_self_connection() {
+        let addr: SocketAddr = "127.0.0.1:18444".parse().unwrap();
+        let local_addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let network = Arc::new(Network::Regtest);
+        let nonces = Arc::new(DashSet::new());
+
+        // Our outbound version reaches our own listener
+        let mut outbound = Handshake::outbound(
+            addr,
+            local_addr,
+            network.clone(),
+            Arc::default(),
+            nonces.clone(),
+        )
+        .expect("outbound");
+        let version = outbound.next_outgoing().expect("version");
+        let mut inbound =
+            Handshake::inbound(local_addr, addr, network, Arc::default(), nonces.clone());
+        assert!(matches!(
+            inbound.receive(version),
+            Err(Error::SelfConnection(_))
+        ));
+
+        // The nonce is forgotten with the outbound handshake
+        drop(outbound);
+        assert!(nonces.is_empty());
+    }
+
+    #[test]
+    fn test_missing_services() {
+        let config = HandshakeConfig {
+            required_services: ServiceFlags::NODE_WITNESS,
+            ..HandshakeConfig::default()
+        };
+        let mut handshake = Handshake::outbound(
+            "127.0.0.1:18444".parse().unwrap(),
+            "127.0.0.1:50000".parse().unwrap(),
+            Arc::new(Network::Regtest),
+            Arc::new(config),
+            Arc::default(),
+        )
+        .expect("outbound");
+
+        // The peer only offers NODE_NETWORK
+        handshake.receive(version()).expect("version");
+        handshake.receive(verack()).expect("verack");
+        assert!(matches!(
+            handshake.pong_timeout(),
+            Err(Error::MissingServices(_, missing)) if missing == ServiceFlags::NODE_WITNESS
+        ));
+    }
+}
@@ -0,0 +1,263 @@
+use crate::config::PingConfig;
+use crate::handshake::count_unknown_command;
+use bitcoin::codec::MessageCodec;
+use bitcoin::feature::Features;
+use bitcoin::message_type::MessageType;
+use bitcoin::network::Network;
+use bitcoin::ping::{Ping, Pong};
+use bitcoin::{Message, Payload, SerdeBitcoinError};
+use dashmap::DashMap;
+use futures::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::{interval, sleep_until, Instant};
+use tokio_util::codec::Framed;
+use tracing::{error, info};
+
+/// Keeps an established connection alive until the peer closes it.
+///
+/// Incoming pings are always answered, announced inventory and relayed transactions are logged.
+/// If `ping` is configured, a ping is sent every interval and the connection is dropped when the
+/// pong does not arrive in time, the round-trip latency of every pong is recorded in `latencies`.
+/// Unknown commands are counted in `unknown_commands` and otherwise ignored, features announced
+/// late by the peer are recorded in `features`.
+pub async fn run<S: AsyncRead + AsyncWrite + Unpin>(
+    framed: &mut Framed<S, MessageCodec>,
+    addr: &SocketAddr,
+    network: &Network,
+    ping: Option<&PingConfig>,
+    latencies: &DashMap<SocketAddr, Duration>,
+    features: &mut Features,
+    unknown_commands: &mut HashMap<String, u64>,
+) -> Result<(), Error> {
+    let send_pings = ping.is_some();
+    let ping = ping.cloned().unwrap_or_default();
+    let pong_timeout = Duration::from_secs(ping.timeout);
+    let mut ticker = interval(Duration::from_secs(ping.interval));
+    let mut pending: Option<(u64, Instant)> = None;
+
+    loop {
+        let deadline = pending
+            .map(|(_, sent)| sent + pong_timeout)
+            .unwrap_or_else(Instant::now);
+
+        tokio::select! {
+            _ = ticker.tick(), if send_pings => {
+                // Only one ping in flight at a time
+                if pending.is_some() {
+                    continue;
+                }
+                let ping = Ping::new();
+                let nonce = *ping.nonce();
+                framed
+                    .send(Message::build(Payload::Ping(ping), MessageType::Ping, network))
+                    .await
+                    .map_err(Error::SendPing)?;
+                pending = Some((nonce, Instant::now()));
+            }
+            _ = sleep_until(deadline), if pending.is_some() => {
+                error!("{addr} did not answer the ping in time, disconnecting");
+                return Err(Error::PongTimeout(pong_timeout));
+            }
+            message = framed.next() => {
+                let Some(message) = message else {
+                    // Connection closed by the peer
+                    return Ok(());
+                };
+                let message = message.map_err(Error::Deserialize)?;
+                if let MessageType::Unknown(command) = message.ty() {
+                    count_unknown_command(unknown_commands, addr, command);
+                    continue;
+                }
+                match message.payload() {
+                    Payload::Ping(ping) => {
+                        let pong = Pong::new(*ping.nonce());
+                        framed
+                            .send(Message::build(Payload::Pong(pong), MessageType::Pong, network))
+                            .await
+                            .map_err(Error::SendPong)?;
+                    }
+                    Payload::Pong(pong) => {
+                        if let Some((nonce, sent)) = pending {
+                            if nonce == *pong.nonce() {
+                                let latency = sent.elapsed();
+                                info!("Latency with {addr}: {latency:?}");
+                                latencies.insert(*addr, latency);
+                                pending = None;
+                            }
+                        }
+                    }
+                    Payload::Inv(inv) => {
+                        for entry in inv.inventory() {
+                            info!("{addr} announced {entry}");
+                        }
+                    }
+                    Payload::Tx(tx) => {
+                        info!(
+                            "{addr} relayed tx {} (wtxid {}, {} inputs, {} outputs, {} WU)",
+                            tx.txid(),
+                            tx.wtxid(),
+                            tx.inputs().len(),
+                            tx.outputs().len(),
+                            tx.weight()
+                        );
+                    }
+                    payload => {
+                        features.record(payload);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Logs the last latency measured with the peer once the connection is closed, next to the
+/// average of the peers still connected, and forgets it
+pub fn report_latency(latencies: &DashMap<SocketAddr, Duration>, addr: &SocketAddr) {
+    let Some((_, latency)) = latencies.remove(addr) else {
+        return;
+    };
+    let connected: Vec<Duration> = latencies.iter().map(|entry| *entry.value()).collect();
+    if connected.is_empty() {
+        info!("Last latency with {addr}: {latency:?}");
+    } else {
+        let average = connected.iter().sum::<Duration>() / connected.len() as u32;
+        info!(
+            "Last latency with {addr}: {latency:?}, average of the {} peers still connected: {average:?}",
+            connected.len()
+        );
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to send the ping message")]
+    SendPing(#[source] SerdeBitcoinError),
+    #[error("Failed to send the pong message")]
+    SendPong(#[source] SerdeBitcoinError),
+    #[error("Failed to deserialize the message")]
+    Deserialize(#[source] SerdeBitcoinError),
+    #[error("No pong received after {0:?}")]
+    PongTimeout(Duration),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::codec::PayloadLimits;
+    use tokio::io::{duplex, DuplexStream};
+
+    /// Both ends of an in-memory connection, the paused clock only advances when no task can make
+    /// progress, which a socket can not tell
+    fn connect() -> (
+        Framed<DuplexStream, MessageCodec>,
+        Framed<DuplexStream, MessageCodec>,
+    ) {
+        let network = Network::Regtest;
+        let (stream, peer) = duplex(64 * 1024);
+        (
+            Framed::new(
+                stream,
+                MessageCodec::new(&network, PayloadLimits::default()),
+            ),
+            Framed::new(peer, MessageCodec::new(&network, PayloadLimits::default())),
+        )
+    }
+
+    fn ping_config() -> PingConfig {
+        PingConfig {
+            interval: 10,
+            timeout: 30,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pong_timeout() {
+        let (mut framed, mut peer) = connect();
+        let addr = "127.0.0.1:18444".parse().unwrap();
+        let latencies = DashMap::new();
+
+        // Stand-in peer receiving the pings without answering them
+        let peer = tokio::spawn(async move { while peer.next().await.is_some() {} });
+
+        let start = Instant::now();
+        let result = run(
+            &mut framed,
+            &addr,
+            &Network::Regtest,
+            Some(&ping_config()),
+            &latencies,
+            &mut Features::default(),
+            &mut HashMap::new(),
+        )
+        .await;
+        peer.abort();
+
+        // Assert that the connection is dropped once the first ping times out
+        assert!(matches!(result, Err(Error::PongTimeout(_))));
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+        assert!(latencies.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_latency() {
+        let (mut framed, mut peer) = connect();
+        let addr = "127.0.0.1:18444".parse().unwrap();
+        let latencies = DashMap::new();
+
+        // Stand-in peer answering two pings after two seconds, a pong with another nonce is
+        // ignored, then it closes the connection
+        let peer = tokio::spawn(async move {
+            let network = Network::Regtest;
+            for _ in 0..2 {
+                let message = peer
+                    .next()
+                    .await
+                    .expect("connection open")
+                    .expect("valid message");
+                let Payload::Ping(ping) = message.payload() else {
+                    panic!("expected a ping");
+                };
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                for nonce in [ping.nonce().wrapping_add(1), *ping.nonce()] {
+                    let pong = Pong::new(nonce);
+                    peer.send(Message::build(
+                        Payload::Pong(pong),
+                        MessageType::Pong,
+                        &network,
+                    ))
+                    .await
+                    .expect("send");
+                }
+            }
+        });
+
+        let start = Instant::now();
+        run(
+            &mut framed,
+            &addr,
+            &Network::Regtest,
+            Some(&ping_config()),
+            &latencies,
+            &mut Features::default(),
+            &mut HashMap::new(),
+        )
+        .await
+        .expect("closed by the peer");
+        peer.await.expect("peer");
+
+        // Assert that the second ping was sent after the interval and its latency was recorded
+        assert_eq!(start.elapsed(), Duration::from_secs(12));
+        assert_eq!(
+            latencies.get(&addr).map(|latency| *latency),
+            Some(Duration::from_secs(2))
+        );
+
+        // Assert that the latency is forgotten once reported
+        report_latency(&latencies, &addr);
+        assert!(latencies.is_empty());
+    }
+}
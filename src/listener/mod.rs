@@ -1,142 +1,292 @@
-use crate::config::Network;
-use bitcoin::message_type::MessageType;
-use bitcoin::verack::VerAck;
-use bitcoin::version::{VersionBuilder, VersionBuilderError};
-use bitcoin::{Message, Payload, SerdeBitcoin, SerdeBitcoinError};
-use dashmap::DashMap;
+use crate::config::{HandshakeConfig, PingConfig};
+use crate::handshake::{self, Handshake};
+use crate::keepalive;
+use bitcoin::codec::MessageCodec;
+use bitcoin::network::Network;
+use dashmap::{DashMap, DashSet};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
-use tracing::{error, info};
-
-#[derive(Default, Debug, Clone)]
-pub enum ConnectionStatus {
-    #[default]
-    NoConnection,
-    Connecting,
-    Connected,
-}
+use tokio_util::codec::Framed;
+use tracing::info;
 
-// @TODO: It doesn't need the DashMap, but if the state were to be shared among the tasks, then it would come quite handy
 pub async fn run(
-    mut stream: TcpStream,
+    stream: TcpStream,
+    codec: MessageCodec,
     network: Arc<Network>,
-    connections: Arc<DashMap<SocketAddr, ConnectionStatus>>,
+    ping: Option<Arc<PingConfig>>,
+    config: Arc<HandshakeConfig>,
+    nonces: Arc<DashSet<u64>>,
+    latencies: Arc<DashMap<SocketAddr, Duration>>,
 ) -> Result<(), Error> {
-    let testnet = network.is_testnet();
     let addr = stream.peer_addr().map_err(Error::FailedToGetPeerAddr)?;
+    let local_addr = stream.local_addr().map_err(Error::LocalAddress)?;
+    let mut framed = Framed::new(stream, codec);
 
-    loop {
-        let status = connections
-            .get(&addr)
-            .map(|v| v.value().clone())
-            .unwrap_or_default();
-        // Read the message
-        let mut br = BufReader::new(&mut stream);
-        let mut response_buffer = br.fill_buf().await.map_err(Error::FillBuffer)?.to_vec();
-        stream.flush().await.map_err(Error::FailedToFlushStream)?;
-
-        if response_buffer.is_empty() {
-            // Connection closed by the peer
-            return Ok(());
-        }
-
-        // Deserialize the response
-        let message: Message = Message::deserialize(&mut response_buffer)
-            .map_err(Error::DeserializeVersionResponse)?;
-
-        let new_status = match status {
-            ConnectionStatus::NoConnection => {
-                if *message.ty() != MessageType::Version {
-                    return Err(Error::ReceivedWrongMessageType(
-                        message.ty().to_string(),
-                        MessageType::Version.to_string(),
-                    ));
-                }
-                send_version(&mut stream, &addr, testnet).await?;
-                ConnectionStatus::Connecting
-            }
-            ConnectionStatus::Connecting => {
-                if *message.ty() != MessageType::VerAck {
-                    return Err(Error::ReceivedWrongMessageType(
-                        message.ty().to_string(),
-                        MessageType::VerAck.to_string(),
-                    ));
-                }
-                send_verack(&mut stream, testnet).await?;
-                info!("Handshake successful with {}", addr);
-                ConnectionStatus::Connected
-            }
-            // If connected accept all the messages
-            ConnectionStatus::Connected => ConnectionStatus::Connected,
-        };
-
-        connections.insert(addr, new_status);
-    }
-}
-
-async fn send_version(
-    stream: &mut TcpStream,
-    addr: &SocketAddr,
-    testnet: bool,
-) -> Result<(), Error> {
-    let version = VersionBuilder::default()
-        .receiver_address(*addr)
-        .sender_address(stream.local_addr().map_err(Error::LocalAddress)?)
-        .build()
-        .map_err(Error::BuildVersionPayload)?;
-    let message = Message::build(Payload::Version(version), MessageType::Version, testnet)
-        .serialize()
-        .map_err(Error::BuildMessage)?;
-
-    // Send the serialized message
-    stream
-        .write_all(&message)
+    let mut handshake = Handshake::inbound(addr, local_addr, network.clone(), config, nonces);
+    handshake::run(&mut framed, &mut handshake)
         .await
-        .map_err(Error::SendVersion)?;
-    stream.flush().await.map_err(Error::FailedToFlushStream)?;
-
-    Ok(())
-}
-
-async fn send_verack(stream: &mut TcpStream, testnet: bool) -> Result<(), Error> {
-    let verack = VerAck;
-    let message = Message::build(Payload::VerAck(verack), MessageType::VerAck, testnet)
-        .serialize()
-        .map_err(Error::BuildMessage)?;
+        .map_err(Error::Handshake)?;
+    let mut features = handshake.features().clone();
+    let mut unknown_commands = handshake.unknown_commands().clone();
+    info!("Handshake successful with {addr} ({features})");
 
-    // Send the serialized message
-    stream
-        .write_all(&message)
-        .await
-        .map_err(Error::SendVerack)?;
-    stream.flush().await.map_err(Error::FailedToFlushStream)?;
+    let result = keepalive::run(
+        &mut framed,
+        &addr,
+        &network,
+        ping.as_deref(),
+        &latencies,
+        &mut features,
+        &mut unknown_commands,
+    )
+    .await
+    .map_err(Error::KeepAlive);
+    keepalive::report_latency(&latencies, &addr);
+    info!("{addr} negotiated features: {features}");
+    if !unknown_commands.is_empty() {
+        info!("{addr} sent unknown commands: {unknown_commands:?}");
+    }
 
-    Ok(())
+    result
 }
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Failed to build the local address")]
     LocalAddress(#[source] std::io::Error),
-    #[error("Failed to build the version payload")]
-    BuildVersionPayload(#[source] VersionBuilderError),
-    #[error("Failed to build the version message")]
-    BuildMessage(#[source] SerdeBitcoinError),
-    #[error("Failed to send the version message")]
-    SendVersion(#[source] std::io::Error),
-    #[error("Failed to send the verack message")]
-    SendVerack(#[source] std::io::Error),
-    #[error("Failed to flush the stream")]
-    FailedToFlushStream(#[source] std::io::Error),
-    #[error("Failed to fill buffer")]
-    FillBuffer(#[source] std::io::Error),
-    #[error("Failed to deserialize the version message response")]
-    DeserializeVersionResponse(#[source] SerdeBitcoinError),
-    #[error("Received wrong message type. Expected {0}, received {1}")]
-    ReceivedWrongMessageType(String, String),
+    #[error("Handshake failed")]
+    Handshake(#[source] handshake::Error),
+    #[error("Failed to keep the connection alive")]
+    KeepAlive(#[source] keepalive::Error),
     #[error("Failed to get peer address")]
     FailedToGetPeerAddr(#[source] std::io::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{sender, test_peer};
+    use bitcoin::addr::SendAddrV2;
+    use bitcoin::codec::PayloadLimits;
+    use bitcoin::feature::{SendHeaders, WtxIdRelay};
+    use bitcoin::message_type::MessageType;
+    use bitcoin::ping::Ping;
+    use bitcoin::verack::VerAck;
+    use bitcoin::version::VersionBuilder;
+    use bitcoin::Payload;
+    use tokio::task::JoinHandle;
+    use tokio::time::timeout;
+
+    /// Runs the listener for a single connection
+    async fn spawn_node(nonces: Arc<DashSet<u64>>) -> (SocketAddr, JoinHandle<Result<(), Error>>) {
+        let (listener, addr) = test_peer::bind().await;
+        let node = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("accept");
+            run(
+                stream,
+                MessageCodec::new(&Network::Regtest, PayloadLimits::default()),
+                Arc::new(Network::Regtest),
+                None,
+                Arc::default(),
+                nonces,
+                Arc::new(DashMap::new()),
+            )
+            .await
+        });
+        (addr, node)
+    }
+
+    /// Connects the scripted peer and sends its version
+    async fn connect(addr: SocketAddr) -> Framed<TcpStream, MessageCodec> {
+        let network = Network::Regtest;
+        let stream = TcpStream::connect(addr).await.expect("connect");
+        let local_addr = stream.local_addr().expect("local address");
+        let mut framed = Framed::new(
+            stream,
+            MessageCodec::new(&network, PayloadLimits::default()),
+        );
+        let version = VersionBuilder::default()
+            .receiver_address(addr)
+            .sender_address(local_addr)
+            .build()
+            .expect("version");
+        test_peer::send(
+            &mut framed,
+            Payload::Version(version),
+            MessageType::Version,
+            &network,
+        )
+        .await;
+        framed
+    }
+
+    async fn send_verack(framed: &mut Framed<TcpStream, MessageCodec>) {
+        test_peer::send(
+            framed,
+            Payload::VerAck(VerAck),
+            MessageType::VerAck,
+            &Network::Regtest,
+        )
+        .await;
+    }
+
+    /// Asserts that the listener answers the version with its version and verack, without
+    /// waiting for our verack
+    async fn assert_version_answered(framed: &mut Framed<TcpStream, MessageCodec>) {
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let message = timeout(Duration::from_secs(5), test_peer::receive(framed))
+                .await
+                .expect("answer without waiting for our verack");
+            received.push(message.ty().clone());
+        }
+        assert_eq!(
+            received,
+            vec![
+                MessageType::Version,
+                MessageType::SendAddrV2,
+                MessageType::VerAck
+            ]
+        );
+    }
+
+    /// Asserts that the handshake is complete because pings are answered
+    async fn assert_connected(framed: &mut Framed<TcpStream, MessageCodec>) {
+        let ping = Ping::new();
+        let nonce = *ping.nonce();
+        test_peer::send(
+            framed,
+            Payload::Ping(ping),
+            MessageType::Ping,
+            &Network::Regtest,
+        )
+        .await;
+        match test_peer::receive(framed).await.payload() {
+            Payload::Pong(pong) => assert_eq!(*pong.nonce(), nonce),
+            payload => panic!("expected a pong, received {payload:?}"),
+        }
+    }
+
+    async fn assert_closed_cleanly(
+        framed: Framed<TcpStream, MessageCodec>,
+        node: JoinHandle<Result<(), Error>>,
+    ) {
+        drop(framed);
+        node.await
+            .expect("node")
+            .expect("connection closed cleanly");
+    }
+
+    #[tokio::test]
+    async fn test_peer_waits_for_our_verack() {
+        let (addr, node) = spawn_node(Arc::default()).await;
+        let mut framed = connect(addr).await;
+
+        // The peer only sends its verack once ours arrived
+        assert_version_answered(&mut framed).await;
+        send_verack(&mut framed).await;
+
+        assert_connected(&mut framed).await;
+        assert_closed_cleanly(framed, node).await;
+    }
+
+    #[tokio::test]
+    async fn test_peer_sends_verack_immediately() {
+        let (addr, node) = spawn_node(Arc::default()).await;
+        let mut framed = connect(addr).await;
+
+        // The peer sends its verack before seeing our version
+        send_verack(&mut framed).await;
+        assert_version_answered(&mut framed).await;
+
+        assert_connected(&mut framed).await;
+        assert_closed_cleanly(framed, node).await;
+    }
+
+    #[tokio::test]
+    async fn test_peer_negotiates_features() {
+        let network = Network::Regtest;
+        let (addr, node) = spawn_node(Arc::default()).await;
+        let mut framed = connect(addr).await;
+
+        // Features before the verack, like a recent Bitcoin Core node
+        test_peer::send(
+            &mut framed,
+            Payload::WtxIdRelay(WtxIdRelay),
+            MessageType::WtxIdRelay,
+            &network,
+        )
+        .await;
+        test_peer::send(
+            &mut framed,
+            Payload::SendAddrV2(SendAddrV2),
+            MessageType::SendAddrV2,
+            &network,
+        )
+        .await;
+        assert_version_answered(&mut framed).await;
+        send_verack(&mut framed).await;
+
+        // And after it
+        test_peer::send(
+            &mut framed,
+            Payload::SendHeaders(SendHeaders),
+            MessageType::SendHeaders,
+            &network,
+        )
+        .await;
+
+        assert_connected(&mut framed).await;
+        assert_closed_cleanly(framed, node).await;
+    }
+
+    #[tokio::test]
+    async fn test_unknown_commands() {
+        let network = Network::Regtest;
+        let (addr, node) = spawn_node(Arc::default()).await;
+        let mut framed = connect(addr).await;
+        let unknown = || {
+            (
+                Payload::Raw(vec![0; 12]),
+                MessageType::Unknown("sendpackages".to_string()),
+            )
+        };
+
+        // Handshake with an unknown command before the verack
+        let (payload, ty) = unknown();
+        test_peer::send(&mut framed, payload, ty, &network).await;
+        send_verack(&mut framed).await;
+        assert_version_answered(&mut framed).await;
+
+        // The connection keeps going after another unknown command
+        let (payload, ty) = unknown();
+        test_peer::send(&mut framed, payload, ty, &network).await;
+        assert_connected(&mut framed).await;
+        assert_closed_cleanly(framed, node).await;
+    }
+
+    #[tokio::test]
+    async fn test_self_connection() {
+        let nonces = Arc::new(DashSet::new());
+        let (addr, node) = spawn_node(nonces.clone()).await;
+
+        // Our own sender connects to the listener
+        let result = sender::run(
+            &addr,
+            Arc::new(Network::Regtest),
+            PayloadLimits::default(),
+            Arc::default(),
+            nonces.clone(),
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(matches!(
+            node.await.expect("node"),
+            Err(Error::Handshake(handshake::Error::SelfConnection(_)))
+        ));
+        assert!(nonces.is_empty());
+    }
+}
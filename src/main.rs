@@ -1,20 +1,39 @@
-use crate::config::{Config, SenderConfig};
+use crate::config::{Config, Mode, SenderConfig};
+use crate::report::{Format, Record};
+use crate::sender::ConnectionInfo;
+use crate::sync::HeaderChain;
+use bitcoin::codec::MessageCodec;
+use bitcoin::hash::Hash256;
+use bitcoin::transaction::Transaction;
 use clap::Parser;
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use futures::future::join_all;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
 use tokio::net::{lookup_host, TcpListener};
-use tokio::task;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::{self, JoinHandle};
+use tokio::time::Instant;
 use tracing::{error, info};
 use tracing_subscriber::FmtSubscriber;
 
+mod broadcast;
 mod config;
+mod crawler;
+mod download;
+mod handshake;
+mod keepalive;
 mod listener;
+mod report;
 mod sender;
+mod sync;
+#[cfg(test)]
+mod test_peer;
 
 const LOCALHOST: &str = "localhost";
 
@@ -32,37 +51,111 @@ async fn main() {
     let config = Config::parse(&PathBuf::from_str(&args.config).expect("Correct path"))
         .expect("Failed to parse config file");
     let mut handles = Vec::new();
+    // Nonces of our outgoing versions, an inbound peer presenting one of them is ourselves
+    let nonces = Arc::new(DashSet::new());
 
     if let Some(sender_config) = config.sender {
         let addresses = get_socket_addresses(&sender_config).await;
-        let network = Arc::new(sender_config.network);
-        for address in addresses {
-            let network_clone = network.clone();
-            let handle = task::spawn(async move {
-                match sender::run(&address, network_clone).await {
-                    Ok(resp) => info!("Handshake successful with {}", resp.addr()),
-                    Err(e) => error!("{e:?}"),
+        match args.mode {
+            Mode::Handshake => {
+                let (records, received) = mpsc::unbounded_channel();
+                let count = addresses.len();
+                handles.extend(spawn_handshakes(
+                    sender_config,
+                    addresses,
+                    nonces.clone(),
+                    records,
+                ));
+                if let Some(format) = args.output {
+                    let writer = open_output(args.output_file.as_deref());
+                    write_report(writer, format, received, count).await;
                 }
-            });
-            handles.push(handle);
+            }
+            Mode::Crawl => {
+                let limiter = sender_config.limiter();
+                let mut records = crawler::run(
+                    addresses,
+                    Arc::new(sender_config.network.clone()),
+                    sender_config
+                        .limits
+                        .payload_limits()
+                        .expect("Invalid payload limits"),
+                    Arc::new(sender_config.handshake.clone()),
+                    nonces.clone(),
+                    limiter,
+                    &sender_config.crawler,
+                )
+                .await;
+                crawler::log_inventory(&records);
+                if let Some(format) = args.output {
+                    let writer = open_output(args.output_file.as_deref());
+                    if let Err(e) = report::write(writer, format, &mut records) {
+                        error!("{e:?}");
+                    }
+                }
+            }
+            Mode::SyncHeaders => sync_headers(&sender_config, &addresses, nonces.clone()).await,
+            Mode::GetBlock => {
+                let hash = args.block.expect("The block hash is required");
+                get_block(&sender_config, &addresses, &hash, nonces.clone()).await
+            }
+            Mode::Broadcast => {
+                let path = args
+                    .transactions
+                    .expect("The transactions file is required");
+                let content = if path.as_os_str() == "-" {
+                    std::io::read_to_string(std::io::stdin())
+                } else {
+                    std::fs::read_to_string(&path)
+                }
+                .expect("Failed to read the transactions");
+                let transactions =
+                    broadcast::parse_transactions(&content).expect("Invalid transactions");
+                handles.extend(spawn_broadcasts(
+                    sender_config,
+                    addresses,
+                    Arc::new(transactions),
+                    nonces.clone(),
+                ))
+            }
         }
     }
 
     // @TODO: The listener could be spawned into a task and then wait for the task
     if let Some(listener_config) = config.listener {
-        let listener = TcpListener::bind(format!("127.0.0.1:{}", listener_config.port))
+        let listener = TcpListener::bind(format!("127.0.0.1:{}", listener_config.port()))
             .await
             .expect("Failed to bind the listener");
+        let payload_limits = listener_config
+            .limits
+            .payload_limits()
+            .expect("Invalid payload limits");
         let network = Arc::new(listener_config.network);
-        let connections = Arc::new(DashMap::new());
+        let ping = listener_config.ping.map(Arc::new);
+        let handshake_config = Arc::new(listener_config.handshake);
+        let latencies = Arc::new(DashMap::new());
 
         info!("Accepting connections");
         loop {
             if let Ok((stream, _)) = listener.accept().await {
                 let network_clone = network.clone();
-                let connections_clone = connections.clone();
+                let ping_clone = ping.clone();
+                let handshake_config_clone = handshake_config.clone();
+                let nonces_clone = nonces.clone();
+                let latencies_clone = latencies.clone();
+                let codec = MessageCodec::new(&network, payload_limits.clone());
                 tokio::spawn(async move {
-                    match listener::run(stream, network_clone, connections_clone).await {
+                    match listener::run(
+                        stream,
+                        codec,
+                        network_clone,
+                        ping_clone,
+                        handshake_config_clone,
+                        nonces_clone,
+                        latencies_clone,
+                    )
+                    .await
+                    {
                         Ok(()) => info!("Connection close"),
                         Err(e) => error!("{e:?}"),
                     }
@@ -77,18 +170,330 @@ async fn main() {
     let _ = join_all(handles).await;
 }
 
+/// Performs the handshake with every address concurrently
+fn spawn_handshakes(
+    config: SenderConfig,
+    addresses: Vec<SocketAddr>,
+    nonces: Arc<DashSet<u64>>,
+    records: UnboundedSender<Record>,
+) -> Vec<JoinHandle<()>> {
+    let mut handles = Vec::new();
+    let limiter = config.limiter();
+    let network = Arc::new(config.network);
+    let ping = config.ping.map(Arc::new);
+    let get_addr = config.get_addr;
+    let payload_limits = config
+        .limits
+        .payload_limits()
+        .expect("Invalid payload limits");
+    let handshake_config = Arc::new(config.handshake);
+    let latencies = Arc::new(DashMap::new());
+    for address in addresses {
+        let network_clone = network.clone();
+        let ping_clone = ping.clone();
+        let handshake_config_clone = handshake_config.clone();
+        let nonces_clone = nonces.clone();
+        let latencies_clone = latencies.clone();
+        let records_clone = records.clone();
+        let limiter_clone = limiter.clone();
+        let payload_limits_clone = payload_limits.clone();
+        let handle = task::spawn(async move {
+            // The slot is freed once the handshake is done, even if the connection is kept alive
+            let permit = limiter_clone.acquire().await;
+            let start = Instant::now();
+            let result = sender::run(
+                &address,
+                network_clone.clone(),
+                payload_limits_clone,
+                handshake_config_clone,
+                nonces_clone,
+            )
+            .await;
+            drop(permit);
+            // Nobody receives the records without --output
+            let _ = records_clone.send(match &result {
+                Ok((resp, _)) => Record::success(resp, start.elapsed()),
+                Err(e) => Record::failure(address, e, start.elapsed()),
+            });
+            match result {
+                Ok((mut resp, mut framed)) => {
+                    log_summary(&resp);
+                    if get_addr {
+                        let received = resp.take_received();
+                        match sender::get_addr(&mut framed, resp.addr(), &network_clone, received)
+                            .await
+                        {
+                            Ok(addresses) => {
+                                info!(
+                                    "{} sent {} addresses (addrv2: {})",
+                                    resp.addr(),
+                                    addresses.len(),
+                                    resp.features().addr_v2()
+                                );
+                                for address in addresses {
+                                    info!("{} knows {address}", resp.addr());
+                                }
+                            }
+                            Err(e) => error!("{e:?}"),
+                        }
+                    }
+                    let mut features = resp.features().clone();
+                    let mut unknown_commands = resp.unknown_commands().clone();
+                    if let Some(ping) = ping_clone {
+                        match keepalive::run(
+                            &mut framed,
+                            resp.addr(),
+                            &network_clone,
+                            Some(&ping),
+                            &latencies_clone,
+                            &mut features,
+                            &mut unknown_commands,
+                        )
+                        .await
+                        {
+                            Ok(()) => info!("Connection with {} closed", resp.addr()),
+                            Err(e) => error!("{e:?}"),
+                        }
+                        keepalive::report_latency(&latencies_clone, resp.addr());
+                    }
+                    if !unknown_commands.is_empty() {
+                        info!(
+                            "{} sent unknown commands: {unknown_commands:?}",
+                            resp.addr()
+                        );
+                    }
+                }
+                Err(e) => error!("{e:?}"),
+            }
+        });
+        handles.push(handle);
+    }
+    handles
+}
+
+/// Broadcasts the transactions to every address concurrently
+fn spawn_broadcasts(
+    config: SenderConfig,
+    addresses: Vec<SocketAddr>,
+    transactions: Arc<Vec<Transaction>>,
+    nonces: Arc<DashSet<u64>>,
+) -> Vec<JoinHandle<()>> {
+    let limiter = config.limiter();
+    let network = Arc::new(config.network);
+    let payload_limits = config
+        .limits
+        .payload_limits()
+        .expect("Invalid payload limits");
+    // Both sides must send wtxidrelay to announce by wtxid
+    let wtxid_relay = config.handshake.features.wtxid_relay;
+    let handshake_config = Arc::new(config.handshake);
+    addresses
+        .into_iter()
+        .map(|address| {
+            let network_clone = network.clone();
+            let handshake_config_clone = handshake_config.clone();
+            let nonces_clone = nonces.clone();
+            let transactions_clone = transactions.clone();
+            let limiter_clone = limiter.clone();
+            let payload_limits_clone = payload_limits.clone();
+            task::spawn(async move {
+                // Held while broadcasting, the peers are asked one slot at a time
+                let _permit = limiter_clone.acquire().await;
+                match sender::run(
+                    &address,
+                    network_clone.clone(),
+                    payload_limits_clone,
+                    handshake_config_clone,
+                    nonces_clone,
+                )
+                .await
+                {
+                    Ok((resp, mut framed)) => {
+                        log_summary(&resp);
+                        match broadcast::run(
+                            &mut framed,
+                            resp.addr(),
+                            &network_clone,
+                            &transactions_clone,
+                            wtxid_relay && *resp.features().wtxid_relay(),
+                            broadcast::BROADCAST_TIMEOUT,
+                        )
+                        .await
+                        {
+                            Ok(outcomes) => {
+                                for (txid, outcome) in outcomes {
+                                    info!("{}: tx {txid} {outcome}", resp.addr());
+                                }
+                            }
+                            Err(e) => error!("{e:?}"),
+                        }
+                    }
+                    Err(e) => error!("{e:?}"),
+                }
+            })
+        })
+        .collect()
+}
+
+/// Syncs the header chain from the first address that completes it, the progress made with a
+/// failing peer is kept for the next one
+async fn sync_headers(config: &SenderConfig, addresses: &[SocketAddr], nonces: Arc<DashSet<u64>>) {
+    let network = Arc::new(config.network.clone());
+    let payload_limits = config
+        .limits
+        .payload_limits()
+        .expect("Invalid payload limits");
+    let handshake_config = Arc::new(config.handshake.clone());
+    let mut chain = HeaderChain::load(&config.headers_file(), &network)
+        .expect("Failed to load the headers file");
+    info!("Loaded {} headers with tip {}", chain.height(), chain.tip());
+    let limiter = config.limiter();
+
+    for address in addresses {
+        let _permit = limiter.acquire().await;
+        match sender::run(
+            address,
+            network.clone(),
+            payload_limits.clone(),
+            handshake_config.clone(),
+            nonces.clone(),
+        )
+        .await
+        {
+            Ok((resp, mut framed)) => {
+                log_summary(&resp);
+                match sync::run(&mut framed, resp.addr(), &network, &mut chain).await {
+                    Ok(()) => {
+                        info!(
+                            "Synced {} headers from {}, best block {}",
+                            chain.height(),
+                            resp.addr(),
+                            chain.tip()
+                        );
+                        return;
+                    }
+                    Err(e) => error!("{e:?}"),
+                }
+            }
+            Err(e) => error!("{e:?}"),
+        }
+    }
+
+    error!("No peer completed the header sync");
+}
+
+/// Downloads and validates the block from the first address that has it
+async fn get_block(
+    config: &SenderConfig,
+    addresses: &[SocketAddr],
+    hash: &Hash256,
+    nonces: Arc<DashSet<u64>>,
+) {
+    let network = Arc::new(config.network.clone());
+    let payload_limits = config
+        .limits
+        .payload_limits()
+        .expect("Invalid payload limits");
+    let handshake_config = Arc::new(config.handshake.clone());
+    let limiter = config.limiter();
+
+    for address in addresses {
+        let _permit = limiter.acquire().await;
+        match sender::run(
+            address,
+            network.clone(),
+            payload_limits.clone(),
+            handshake_config.clone(),
+            nonces.clone(),
+        )
+        .await
+        {
+            Ok((resp, mut framed)) => {
+                log_summary(&resp);
+                match download::block(&mut framed, resp.addr(), &network, hash).await {
+                    Ok(block) => {
+                        info!(
+                            "Block {hash} from {} is valid: {} transactions, {} bytes, {} bytes stripped, {} WU, witness commitment: {}",
+                            resp.addr(),
+                            block.transactions().len(),
+                            block.total_size(),
+                            block.stripped_size(),
+                            block.weight(),
+                            block.witness_commitment().is_some()
+                        );
+                        return;
+                    }
+                    Err(e) => error!("{e:?}"),
+                }
+            }
+            Err(e) => error!("{e:?}"),
+        }
+    }
+
+    error!("No peer sent a valid block {hash}");
+}
+
+/// Opens the file the records are written to, stdout without a path
+fn open_output(path: Option<&Path>) -> Box<dyn Write> {
+    match path {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).expect("Failed to create the output file"),
+        )),
+        None => Box::new(std::io::stdout().lock()),
+    }
+}
+
+/// Waits for the record of every target and writes them
+async fn write_report(
+    writer: impl Write,
+    format: Format,
+    mut records: UnboundedReceiver<Record>,
+    count: usize,
+) {
+    let mut received = Vec::with_capacity(count);
+    while received.len() < count {
+        match records.recv().await {
+            Some(record) => received.push(record),
+            None => break,
+        }
+    }
+    if let Err(e) = report::write(writer, format, &mut received) {
+        error!("{e:?}");
+    }
+}
+
+/// Logs what the peer announced in its version and negotiated during the handshake
+fn log_summary(info: &ConnectionInfo) {
+    info!(
+        addr = %info.addr(),
+        protocol_version = info.protocol_version(),
+        user_agent = info.user_agent(),
+        services = %info.services(),
+        start_height = info.start_height(),
+        relay = info.relay(),
+        clock_offset_secs = info.clock_offset(),
+        latency_ms = info.latency().as_millis() as u64,
+        features = %info.features(),
+        "Handshake successful"
+    );
+}
+
 async fn get_socket_addresses(config: &SenderConfig) -> Vec<SocketAddr> {
-    // @TODO: This is definitely not elegant neither nice, the type of connection should be an enum
-    if config.dns_seed.contains(LOCALHOST) {
-        vec![SocketAddr::new(
-            Ipv4Addr::new(127, 0, 0, 1).into(),
-            config.port,
-        )]
-    } else {
-        // @TODO: It shouldn't panic here, the error should be propagated accordingly
-        lookup_host((config.dns_seed.clone(), config.port))
-            .await
-            .expect("host not found")
-            .collect()
+    let port = config.port();
+    let mut addresses = Vec::new();
+
+    for dns_seed in config.dns_seeds() {
+        // @TODO: This is definitely not elegant neither nice, the type of connection should be an enum
+        if dns_seed.contains(LOCALHOST) {
+            addresses.push(SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), port));
+            continue;
+        }
+
+        match lookup_host((dns_seed.as_str(), port)).await {
+            Ok(resolved) => addresses.extend(resolved),
+            Err(e) => error!("Failed to resolve {dns_seed}: {e}"),
+        }
     }
+
+    addresses
 }
@@ -0,0 +1,179 @@
+use crate::sender::{self, ConnectionInfo};
+use bitcoin::services::ServiceFlags;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Format of the records written with `--output`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// One JSON object per line
+    Json,
+    /// Comma separated values with a header row
+    Csv,
+}
+
+/// Outcome of the handshake with one target.
+///
+/// The fields are written in this order, the ones only known after a successful handshake are
+/// left empty when it failed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Record {
+    pub address: SocketAddr,
+    pub success: bool,
+    /// Kind of the failure, see `sender::Error::kind`
+    pub error: Option<&'static str>,
+    pub protocol_version: Option<i32>,
+    pub services: Option<ServiceFlags>,
+    pub user_agent: Option<String>,
+    pub start_height: Option<i32>,
+    pub relay: Option<bool>,
+    pub features: Option<String>,
+    pub clock_offset_secs: Option<i64>,
+    /// From sending our version until the verack of the peer arrived
+    pub latency_ms: Option<u64>,
+    /// From connecting until the handshake completed or failed
+    pub duration_ms: u64,
+}
+
+impl Record {
+    pub fn success(info: &ConnectionInfo, duration: Duration) -> Self {
+        Self {
+            address: *info.addr(),
+            success: true,
+            error: None,
+            protocol_version: Some(*info.protocol_version()),
+            services: Some(*info.services()),
+            user_agent: Some(info.user_agent().clone()),
+            start_height: Some(*info.start_height()),
+            relay: Some(*info.relay()),
+            features: Some(info.features().to_string()),
+            clock_offset_secs: Some(*info.clock_offset()),
+            latency_ms: Some(info.latency().as_millis() as u64),
+            duration_ms: duration.as_millis() as u64,
+        }
+    }
+
+    pub fn failure(address: SocketAddr, error: &sender::Error, duration: Duration) -> Self {
+        Self {
+            address,
+            success: false,
+            error: Some(error.kind()),
+            protocol_version: None,
+            services: None,
+            user_agent: None,
+            start_height: None,
+            relay: None,
+            features: None,
+            clock_offset_secs: None,
+            latency_ms: None,
+            duration_ms: duration.as_millis() as u64,
+        }
+    }
+}
+
+/// Writes the records sorted by address, so the output of two runs can be compared line by line
+pub fn write<W: Write>(writer: W, format: Format, records: &mut [Record]) -> Result<(), Error> {
+    records.sort_by_key(|record| record.address);
+    match format {
+        Format::Json => {
+            let mut writer = writer;
+            for record in records.iter() {
+                serde_json::to_writer(&mut writer, record).map_err(Error::Json)?;
+                writeln!(writer).map_err(Error::Write)?;
+            }
+            writer.flush().map_err(Error::Write)
+        }
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for record in records.iter() {
+                writer.serialize(record).map_err(Error::Csv)?;
+            }
+            writer.flush().map_err(Error::Write)
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to write the report as JSON")]
+    Json(#[source] serde_json::Error),
+    #[error("Failed to write the report as CSV")]
+    Csv(#[source] csv::Error),
+    #[error("Failed to write the report")]
+    Write(#[source] std::io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record {
+                address: "127.0.0.2:8333".parse().unwrap(),
+                success: true,
+                error: None,
+                protocol_version: Some(70016),
+                services: Some(ServiceFlags::NODE_NETWORK | ServiceFlags::NODE_WITNESS),
+                user_agent: Some("/Satoshi:27.0.0/".to_string()),
+                start_height: Some(840_000),
+                relay: Some(true),
+                features: Some("wtxidrelay, addrv2".to_string()),
+                clock_offset_secs: Some(-2),
+                latency_ms: Some(120),
+                duration_ms: 180,
+            },
+            Record::failure(
+                "127.0.0.1:8333".parse().unwrap(),
+                &sender::Error::ConnectionClosed,
+                Duration::from_millis(15),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_json() {
+        let mut output = Vec::new();
+        write(&mut output, Format::Json, &mut records()).expect("write");
+
+        // Assert that there is one object per line, sorted by address
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                r#"{"address":"127.0.0.1:8333","success":false,"error":"connection_closed","#,
+                r#""protocol_version":null,"services":null,"user_agent":null,"#,
+                r#""start_height":null,"relay":null,"features":null,"#,
+                r#""clock_offset_secs":null,"latency_ms":null,"duration_ms":15}"#,
+                "\n",
+                r#"{"address":"127.0.0.2:8333","success":true,"error":null,"#,
+                r#""protocol_version":70016,"services":"NODE_NETWORK | NODE_WITNESS","#,
+                r#""user_agent":"/Satoshi:27.0.0/","start_height":840000,"relay":true,"#,
+                r#""features":"wtxidrelay, addrv2","clock_offset_secs":-2,"latency_ms":120,"#,
+                r#""duration_ms":180}"#,
+                "\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut output = Vec::new();
+        write(&mut output, Format::Csv, &mut records()).expect("write");
+
+        // Assert that the header comes first and the failed handshake leaves the fields empty
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "address,success,error,protocol_version,services,user_agent,start_height,",
+                "relay,features,clock_offset_secs,latency_ms,duration_ms\n",
+                "127.0.0.1:8333,false,connection_closed,,,,,,,,,15\n",
+                "127.0.0.2:8333,true,,70016,NODE_NETWORK | NODE_WITNESS,/Satoshi:27.0.0/,",
+                "840000,true,\"wtxidrelay, addrv2\",-2,120,180\n",
+            )
+        );
+    }
+}
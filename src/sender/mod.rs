@@ -1,111 +1,244 @@
-use crate::config::Network;
+use crate::config::HandshakeConfig;
+use crate::handshake::{self, Handshake};
+use bitcoin::addr::{GetAddr, NetAddrV2};
+use bitcoin::codec::{MessageCodec, PayloadLimits};
+use bitcoin::feature::Features;
 use bitcoin::message_type::MessageType;
-use bitcoin::verack::VerAck;
-use bitcoin::version::{VersionBuilder, VersionBuilderError};
-use bitcoin::{Message, Payload, SerdeBitcoin, SerdeBitcoinError};
+use bitcoin::network::Network;
+use bitcoin::services::ServiceFlags;
+use bitcoin::{Message, Payload, SerdeBitcoinError};
+use dashmap::DashSet;
+use futures::{SinkExt, StreamExt};
 use getset::Getters;
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use thiserror::Error;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio::time::error::Elapsed;
-use tokio::time::timeout;
+use tokio::time::{sleep_until, timeout, Instant};
+use tokio_util::codec::Framed;
 use tracing::{error, info};
 
 const CONNECTION_TIMEOUT: Duration = Duration::from_secs(15);
-const VERSION_TIMEOUT: Duration = Duration::from_secs(30);
-const VERACK_TIMEOUT: Duration = Duration::from_secs(30);
+const GETADDR_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// What is known about the peer once the handshake is complete
 #[derive(Getters)]
-// @TODO: Add more fields from the node response as needed
 pub struct ConnectionInfo {
     #[getset(get = "pub")]
     addr: SocketAddr,
+
+    #[getset(get = "pub")]
+    protocol_version: i32,
+
+    #[getset(get = "pub")]
+    services: ServiceFlags,
+
+    #[getset(get = "pub")]
+    user_agent: String,
+
+    /// Height of the best block of the peer when it connected
+    #[getset(get = "pub")]
+    start_height: i32,
+
+    /// The peer relays transactions
+    #[getset(get = "pub")]
+    relay: bool,
+
+    /// Clock of the peer minus ours, in seconds
+    #[getset(get = "pub")]
+    clock_offset: i64,
+
+    /// From sending our version until the verack of the peer arrived
+    #[getset(get = "pub")]
+    latency: Duration,
+
+    /// Features negotiated by the peer before and right after the verack
+    #[getset(get = "pub")]
+    features: Features,
+
+    /// Commands not part of `MessageType` received during the handshake
+    #[getset(get = "pub")]
+    unknown_commands: HashMap<String, u64>,
+
+    /// Messages received during the handshake that it did not need
+    received: Vec<Message>,
 }
 
-pub async fn run(addr: &SocketAddr, network: Arc<Network>) -> Result<ConnectionInfo, Error> {
-    info!("Connecting to {addr}");
-    let mut stream = timeout(CONNECTION_TIMEOUT, TcpStream::connect(addr))
-        .await
-        .map_err(Error::ConnectionTimeout)?
-        .map_err(|e| Error::TcpConnection(addr.to_string(), e))?;
+impl ConnectionInfo {
+    /// Takes the messages received during the handshake that it did not need, in order
+    pub fn take_received(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.received)
+    }
+}
 
-    let testnet = network.is_testnet();
-    // @TODO: Improvement: To add a retry mechanism
-    let resp_version = timeout(VERSION_TIMEOUT, version(&mut stream, addr, testnet))
-        .await
-        .map_err(Error::VersionTimeout)??;
+/// Bounds the outbound handshakes in progress and how often new connections are opened, the
+/// clones share the limits
+#[derive(Clone)]
+pub struct Limiter {
+    slots: Option<Arc<Semaphore>>,
+    interval: Option<Duration>,
+    next_connection: Arc<Mutex<Instant>>,
+}
 
-    if *resp_version.ty() != MessageType::Version {
-        return Err(Error::ReceivedWrongMessageType(
-            resp_version.ty().to_string(),
-            MessageType::Version.to_string(),
-        ));
+impl Limiter {
+    pub fn new(max_concurrent: Option<NonZeroUsize>, per_second: Option<NonZeroU32>) -> Self {
+        Self {
+            slots: max_concurrent.map(|max| Arc::new(Semaphore::new(max.get()))),
+            interval: per_second.map(|rate| Duration::from_secs(1) / rate.get()),
+            next_connection: Arc::new(Mutex::new(Instant::now())),
+        }
     }
-    stream.flush().await.map_err(Error::FailedToFlushStream)?;
 
-    let resp_verack = timeout(VERACK_TIMEOUT, verack(&mut stream, testnet))
-        .await
-        .map_err(Error::VerackTimeout)??;
-    if *resp_verack.ty() != MessageType::VerAck {
-        return Err(Error::ReceivedWrongMessageType(
-            resp_verack.ty().to_string(),
-            MessageType::VerAck.to_string(),
-        ));
+    /// Waits for a free slot and for the turn of the next connection. The slot is held until the
+    /// returned permit is dropped, the waiting targets get the slots in order.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        let permit = match &self.slots {
+            Some(slots) => Some(
+                slots
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("the semaphore is never closed"),
+            ),
+            None => None,
+        };
+        if let Some(interval) = self.interval {
+            let turn = {
+                let mut next_connection = self.next_connection.lock().expect("lock");
+                let turn = (*next_connection).max(Instant::now());
+                *next_connection = turn + interval;
+                turn
+            };
+            sleep_until(turn).await;
+        }
+        permit
+    }
+}
+
+impl Default for Limiter {
+    /// Nothing waits
+    fn default() -> Self {
+        Self::new(None, None)
     }
-    Ok(ConnectionInfo { addr: *addr })
 }
 
-async fn version(
-    stream: &mut TcpStream,
+/// Performs the handshake, the returned stream can be used to keep talking to the peer
+pub async fn run(
     addr: &SocketAddr,
-    testnet: bool,
-) -> Result<Message, Error> {
-    let version = VersionBuilder::default()
-        .receiver_address(*addr)
-        .sender_address(stream.local_addr().map_err(Error::LocalAddress)?)
-        .build()
-        .map_err(Error::BuildVersionPayload)?;
-    let message = Message::build(Payload::Version(version), MessageType::Version, testnet)
-        .serialize()
-        .map_err(Error::BuildMessage)?;
-
-    // Send the serialized message
-    stream
-        .write_all(&message)
+    network: Arc<Network>,
+    limits: PayloadLimits,
+    config: Arc<HandshakeConfig>,
+    nonces: Arc<DashSet<u64>>,
+) -> Result<(ConnectionInfo, Framed<TcpStream, MessageCodec>), Error> {
+    info!("Connecting to {addr}");
+    let stream = timeout(CONNECTION_TIMEOUT, TcpStream::connect(addr))
         .await
-        .map_err(Error::SendVersion)?;
-    stream.flush().await.map_err(Error::FailedToFlushStream)?;
+        .map_err(Error::ConnectionTimeout)?
+        .map_err(|e| Error::TcpConnection(addr.to_string(), e))?;
+    let local_addr = stream.local_addr().map_err(Error::LocalAddress)?;
+    let mut framed = Framed::new(stream, MessageCodec::new(&network, limits));
 
-    // Read the response
-    let mut br = BufReader::new(stream);
-    let mut response_buffer = br.fill_buf().await.map_err(Error::FillBuffer)?.to_vec();
+    // @TODO: Improvement: To add a retry mechanism
+    let mut handshake = Handshake::outbound(*addr, local_addr, network, config, nonces)
+        .map_err(Error::Handshake)?;
+    let timings = handshake::run(&mut framed, &mut handshake)
+        .await
+        .map_err(Error::Handshake)?;
+    let version = handshake
+        .peer_version()
+        .as_ref()
+        .expect("the version is received before the handshake completes");
 
-    // Deserialize the response
-    Message::deserialize(&mut response_buffer).map_err(Error::DeserializeVersionResponse)
+    Ok((
+        ConnectionInfo {
+            addr: *addr,
+            protocol_version: *version.protocol_version(),
+            services: *version.services(),
+            user_agent: version.user_agent().clone(),
+            start_height: *version.start_height(),
+            relay: *version.relay(),
+            clock_offset: timings.clock_offset,
+            latency: timings.latency,
+            features: handshake.features().clone(),
+            unknown_commands: handshake.unknown_commands().clone(),
+            received: handshake.take_received(),
+        },
+        framed,
+    ))
 }
 
-async fn verack(stream: &mut TcpStream, testnet: bool) -> Result<Message, Error> {
-    let verack = VerAck;
-    let message = Message::build(Payload::VerAck(verack), MessageType::VerAck, testnet)
-        .serialize()
-        .map_err(Error::BuildMessage)?;
+/// Requests the addresses known by the peer.
+///
+/// Peers usually announce themselves with a single address right after the handshake, so the
+/// addresses are collected until a longer list arrives or the timeout expires. The ones in the
+/// messages `received` during the handshake come first.
+pub async fn get_addr(
+    framed: &mut Framed<TcpStream, MessageCodec>,
+    addr: &SocketAddr,
+    network: &Network,
+    received: Vec<Message>,
+) -> Result<Vec<NetAddrV2>, Error> {
+    let message = Message::build(Payload::GetAddr(GetAddr), MessageType::GetAddr, network);
+    framed.send(message).await.map_err(Error::SendGetAddr)?;
 
-    // Send the serialized message
-    stream
-        .write_all(&message)
-        .await
-        .map_err(Error::SendVerack)?;
-    stream.flush().await.map_err(Error::FailedToFlushStream)?;
+    let mut addresses: Vec<NetAddrV2> =
+        received.iter().filter_map(addresses_in).flatten().collect();
+    let response = timeout(GETADDR_TIMEOUT, async {
+        loop {
+            let message = receive(framed, addr, Error::DeserializeAddrResponse).await?;
+            let Some(received) = addresses_in(&message) else {
+                continue;
+            };
+
+            let done = received.len() > 1;
+            addresses.extend(received);
+            if done {
+                return Ok(());
+            }
+        }
+    })
+    .await;
+
+    match response {
+        Ok(result) => result.map(|()| addresses),
+        // Keep what was received so far
+        Err(_) => Ok(addresses),
+    }
+}
 
-    // Read the response
-    let mut br = BufReader::new(stream);
-    let mut response_buffer = br.fill_buf().await.map_err(Error::FillBuffer)?.to_vec();
+/// Addresses of an addr or addrv2 message
+fn addresses_in(message: &Message) -> Option<Vec<NetAddrV2>> {
+    match message.payload() {
+        Payload::Addr(addr) => Some(
+            addr.addresses()
+                .iter()
+                .cloned()
+                .map(NetAddrV2::from)
+                .collect(),
+        ),
+        Payload::AddrV2(addr) => Some(addr.addresses().clone()),
+        _ => None,
+    }
+}
 
-    // Deserialize the response
-    Message::deserialize(&mut response_buffer).map_err(Error::DeserializeVerackResponse)
+async fn receive(
+    framed: &mut Framed<TcpStream, MessageCodec>,
+    addr: &SocketAddr,
+    deserialize_error: fn(SerdeBitcoinError) -> Error,
+) -> Result<Message, Error> {
+    match framed.next().await.ok_or(Error::ConnectionClosed)? {
+        Ok(message) => Ok(message),
+        Err(e @ SerdeBitcoinError::InvalidMagicBytes(..)) => {
+            error!("{addr} sent a message for another network, disconnecting");
+            Err(Error::WrongNetwork(e))
+        }
+        Err(e) => Err(deserialize_error(e)),
+    }
 }
 
 #[derive(Error, Debug)]
@@ -114,28 +247,351 @@ pub enum Error {
     TcpConnection(String, #[source] std::io::Error),
     #[error("Failed to build the local address")]
     LocalAddress(#[source] std::io::Error),
-    #[error("Failed to build the version payload")]
-    BuildVersionPayload(#[source] VersionBuilderError),
-    #[error("Failed to build the version message")]
-    BuildMessage(#[source] SerdeBitcoinError),
-    #[error("Failed to send the version message")]
-    SendVersion(#[source] std::io::Error),
-    #[error("Failed to send the verack message")]
-    SendVerack(#[source] std::io::Error),
-    #[error("Failed to flush the stream")]
-    FailedToFlushStream(#[source] std::io::Error),
-    #[error("Failed to fill buffer")]
-    FillBuffer(#[source] std::io::Error),
-    #[error("Failed to deserialize the version message response")]
-    DeserializeVersionResponse(#[source] SerdeBitcoinError),
-    #[error("Failed to deserialize the verack message response")]
-    DeserializeVerackResponse(#[source] SerdeBitcoinError),
-    #[error("Version timeout")]
-    VersionTimeout(#[source] Elapsed),
-    #[error("Verack timeout")]
-    VerackTimeout(#[source] Elapsed),
     #[error("Connection timeout")]
     ConnectionTimeout(#[source] Elapsed),
-    #[error("Received wrong message type. Expected {0}, received {1}")]
-    ReceivedWrongMessageType(String, String),
+    #[error("Handshake failed")]
+    Handshake(#[source] handshake::Error),
+    #[error("Failed to send the getaddr message")]
+    SendGetAddr(#[source] SerdeBitcoinError),
+    #[error("Failed to deserialize the getaddr message response")]
+    DeserializeAddrResponse(#[source] SerdeBitcoinError),
+    #[error("Connection closed by the peer")]
+    ConnectionClosed,
+    #[error("Peer is on a different network")]
+    WrongNetwork(#[source] SerdeBitcoinError),
+}
+
+impl Error {
+    /// Short name of the failure that stays the same across runs, handshake failures are named
+    /// after the step that failed
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::TcpConnection(..) => "tcp_connection",
+            Error::LocalAddress(_) => "local_address",
+            Error::ConnectionTimeout(_) => "connection_timeout",
+            Error::Handshake(e) => e.kind(),
+            Error::SendGetAddr(_) => "send_getaddr",
+            Error::DeserializeAddrResponse(_) => "deserialize_addr_response",
+            Error::ConnectionClosed => "connection_closed",
+            Error::WrongNetwork(_) => "wrong_network",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::FeaturesConfig;
+    use crate::test_peer;
+    use bitcoin::addr::{Addr, NetAddr, SendAddrV2};
+    use bitcoin::feature::{FeeFilter, SendCmpct, SendHeaders, SendTxRcncl, WtxIdRelay};
+    use bitcoin::ping::Pong;
+    use bitcoin::reject::{Reject, RejectCode};
+    use bitcoin::verack::VerAck;
+    use bitcoin::version::VersionBuilder;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limiter() {
+        let limiter = Limiter::new(NonZeroUsize::new(2), NonZeroU32::new(10));
+
+        let start = Instant::now();
+
+        let first = limiter.acquire().await;
+        let _second = limiter.acquire().await;
+
+        // Assert that the connections are spaced by the rate
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+
+        // Assert that a third target waits until a slot is free
+        let third = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire().await }
+        });
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!third.is_finished());
+
+        drop(first);
+        assert!(third.await.expect("third").is_some());
+
+        // Without limits nothing waits
+        let start = Instant::now();
+        let unlimited = Limiter::default();
+        for _ in 0..3 {
+            assert!(unlimited.acquire().await.is_none());
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_peer_rejected() {
+        let network = Arc::new(Network::Regtest);
+
+        // Stand-in peer rejecting the version like old nodes did with obsolete versions
+        let (listener, addr) = test_peer::bind().await;
+        let peer = tokio::spawn(async move {
+            let network = Network::Regtest;
+            let (stream, _) = listener.accept().await.expect("accept");
+            let mut framed = Framed::new(
+                stream,
+                MessageCodec::new(&network, PayloadLimits::default()),
+            );
+            let message = test_peer::receive(&mut framed).await;
+            assert_eq!(*message.ty(), MessageType::Version);
+
+            let reject = Reject::new(
+                "version".to_string(),
+                RejectCode::Obsolete,
+                "Version must be 31800 or greater".to_string(),
+                None,
+            );
+            test_peer::send(
+                &mut framed,
+                Payload::Reject(reject),
+                MessageType::Reject,
+                &network,
+            )
+            .await;
+        });
+
+        let result = run(
+            &addr,
+            network,
+            PayloadLimits::default(),
+            Arc::default(),
+            Arc::default(),
+        )
+        .await;
+        peer.await.expect("peer");
+
+        match result {
+            Err(Error::Handshake(handshake::Error::PeerRejected(_, reject))) => {
+                assert_eq!(*reject.code(), RejectCode::Obsolete);
+                assert_eq!(reject.reason(), "Version must be 31800 or greater");
+            }
+            _ => panic!("expected a rejection"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_features() {
+        let network = Arc::new(Network::Regtest);
+
+        // Stand-in peer negotiating features like a recent Bitcoin Core node
+        let (listener, addr) = test_peer::bind().await;
+        let peer = tokio::spawn(async move {
+            let network = Network::Regtest;
+            let (stream, addr) = listener.accept().await.expect("accept");
+            let local_addr = stream.local_addr().expect("local address");
+            let mut framed = Framed::new(
+                stream,
+                MessageCodec::new(&network, PayloadLimits::default()),
+            );
+            let message = test_peer::receive(&mut framed).await;
+            assert_eq!(*message.ty(), MessageType::Version);
+
+            // Its clock is two minutes behind ours
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .expect("time")
+                .as_secs() as i64;
+            let version = VersionBuilder::default()
+                .services(ServiceFlags::NODE_NETWORK | ServiceFlags::NODE_WITNESS)
+                .timestamp(now - 120)
+                .receiver_address(addr)
+                .sender_address(local_addr)
+                .user_agent("/Satoshi:27.0.0/".to_string())
+                .start_height(840_000)
+                .relay(true)
+                .build()
+                .expect("version");
+            for (payload, ty) in [
+                (Payload::Version(version), MessageType::Version),
+                (Payload::WtxIdRelay(WtxIdRelay), MessageType::WtxIdRelay),
+                (Payload::SendAddrV2(SendAddrV2), MessageType::SendAddrV2),
+                (
+                    Payload::SendTxRcncl(SendTxRcncl::new(1, 42)),
+                    MessageType::SendTxRcncl,
+                ),
+            ] {
+                test_peer::send(&mut framed, payload, ty, &network).await;
+            }
+
+            // Our features, the verack and the post verack ones are received in order
+            let mut received = Vec::new();
+            loop {
+                let message = test_peer::receive(&mut framed).await;
+                received.push(message.ty().clone());
+                match message.payload() {
+                    Payload::VerAck(_) => {
+                        for (payload, ty) in [
+                            (Payload::VerAck(VerAck), MessageType::VerAck),
+                            (Payload::SendHeaders(SendHeaders), MessageType::SendHeaders),
+                            (
+                                Payload::SendCmpct(SendCmpct::new(false, 2)),
+                                MessageType::SendCmpct,
+                            ),
+                            (
+                                Payload::SendCmpct(SendCmpct::new(false, 1)),
+                                MessageType::SendCmpct,
+                            ),
+                            (
+                                Payload::FeeFilter(FeeFilter::new(1000)),
+                                MessageType::FeeFilter,
+                            ),
+                        ] {
+                            test_peer::send(&mut framed, payload, ty, &network).await;
+                        }
+                    }
+                    Payload::Ping(ping) => {
+                        test_peer::send(
+                            &mut framed,
+                            Payload::Pong(Pong::new(*ping.nonce())),
+                            MessageType::Pong,
+                            &network,
+                        )
+                        .await;
+                        return received;
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let config = HandshakeConfig {
+            features: FeaturesConfig {
+                wtxid_relay: true,
+                send_headers: true,
+                compact_blocks: false,
+                fee_filter: Some(2000),
+            },
+            ..HandshakeConfig::default()
+        };
+        let (info, _framed) = run(
+            &addr,
+            network,
+            PayloadLimits::default(),
+            Arc::new(config),
+            Arc::default(),
+        )
+        .await
+        .expect("handshake");
+        let received = peer.await.expect("peer");
+
+        assert_eq!(
+            received,
+            vec![
+                MessageType::SendAddrV2,
+                MessageType::WtxIdRelay,
+                MessageType::VerAck,
+                MessageType::SendHeaders,
+                MessageType::FeeFilter,
+                MessageType::Ping,
+            ]
+        );
+
+        assert_eq!(*info.protocol_version(), bitcoin::version::PROTOCOL_VERSION);
+        assert_eq!(
+            *info.services(),
+            ServiceFlags::NODE_NETWORK | ServiceFlags::NODE_WITNESS
+        );
+        assert_eq!(info.user_agent(), "/Satoshi:27.0.0/");
+        assert_eq!(*info.start_height(), 840_000);
+        assert!(info.relay());
+        assert!((-121..=-119).contains(info.clock_offset()));
+        assert!(*info.latency() < Duration::from_secs(5));
+
+        let features = info.features();
+        assert!(features.wtxid_relay());
+        assert!(features.addr_v2());
+        assert_eq!(*features.tx_reconciliation(), Some(SendTxRcncl::new(1, 42)));
+        assert!(features.send_headers());
+        assert_eq!(*features.compact_blocks(), Some(SendCmpct::new(false, 2)));
+        assert_eq!(*features.fee_filter(), Some(1000));
+    }
+
+    #[tokio::test]
+    async fn test_get_addr_self_announcement() {
+        let network = Arc::new(Network::Regtest);
+        let self_announcement: SocketAddr = "127.0.0.1:18444".parse().unwrap();
+        let known: Vec<SocketAddr> = vec![
+            "127.0.0.2:18444".parse().unwrap(),
+            "127.0.0.3:18444".parse().unwrap(),
+        ];
+        let addr_message = |addresses: &[SocketAddr]| {
+            Payload::Addr(Addr::new(
+                addresses
+                    .iter()
+                    .map(|&addr| NetAddr::new(0, ServiceFlags::NODE_NETWORK, addr))
+                    .collect(),
+            ))
+        };
+
+        // Stand-in peer announcing itself before answering our ping, like Bitcoin Core may
+        let (listener, addr) = test_peer::bind().await;
+        let peer = tokio::spawn({
+            let known = known.clone();
+            async move {
+                let network = Network::Regtest;
+                let (stream, addr) = listener.accept().await.expect("accept");
+                let local_addr = stream.local_addr().expect("local address");
+                let mut framed = Framed::new(
+                    stream,
+                    MessageCodec::new(&network, PayloadLimits::default()),
+                );
+                loop {
+                    let message = test_peer::receive(&mut framed).await;
+                    let (payload, ty) = match message.payload() {
+                        Payload::Version(_) => {
+                            let version = VersionBuilder::default()
+                                .receiver_address(addr)
+                                .sender_address(local_addr)
+                                .build()
+                                .expect("version");
+                            (Payload::Version(version), MessageType::Version)
+                        }
+                        Payload::VerAck(_) => (Payload::VerAck(VerAck), MessageType::VerAck),
+                        Payload::Ping(ping) => {
+                            test_peer::send(
+                                &mut framed,
+                                addr_message(&[self_announcement]),
+                                MessageType::Addr,
+                                &network,
+                            )
+                            .await;
+                            (Payload::Pong(Pong::new(*ping.nonce())), MessageType::Pong)
+                        }
+                        Payload::GetAddr(_) => (addr_message(&known), MessageType::Addr),
+                        _ => continue,
+                    };
+                    test_peer::send(&mut framed, payload, ty, &network).await;
+                }
+            }
+        });
+
+        let (mut info, mut framed) = run(
+            &addr,
+            network.clone(),
+            PayloadLimits::default(),
+            Arc::default(),
+            Arc::default(),
+        )
+        .await
+        .expect("handshake");
+        let received = info.take_received();
+        let addresses = get_addr(&mut framed, &addr, &network, received)
+            .await
+            .expect("addresses");
+        peer.abort();
+
+        // Assert that the self-announcement received during the handshake comes first
+        let mut expected = vec![self_announcement];
+        expected.extend(known);
+        assert_eq!(
+            addresses
+                .iter()
+                .filter_map(NetAddrV2::socket_addr)
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
 }
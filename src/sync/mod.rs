@@ -0,0 +1,444 @@
+use bitcoin::block_header::{BlockHeader, Work};
+use bitcoin::codec::MessageCodec;
+use bitcoin::hash::Hash256;
+use bitcoin::headers::{block_locator, GetHeaders, MAX_HEADERS_RESULTS};
+use bitcoin::message_type::MessageType;
+use bitcoin::network::Network;
+use bitcoin::ping::Pong;
+use bitcoin::version::PROTOCOL_VERSION;
+use bitcoin::{Message, Payload, SerdeBitcoin, SerdeBitcoinError};
+use futures::{SinkExt, StreamExt};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tokio::time::timeout;
+use tokio_util::codec::Framed;
+use tracing::info;
+
+const HEADERS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Best header chain known locally.
+///
+/// The file stores the serialized headers one after the other, starting with the genesis block.
+pub struct HeaderChain {
+    network: Network,
+    path: PathBuf,
+    headers: Vec<BlockHeader>,
+    hashes: Vec<Hash256>,
+    /// Work accumulated up to every header, the genesis block included
+    chain_work: Vec<Work>,
+    /// Number of headers already written to the file
+    persisted: usize,
+}
+
+impl HeaderChain {
+    /// Loads and validates the chain stored in `path`, a missing file starts from the genesis
+    /// block
+    pub fn load(path: &Path, network: &Network) -> Result<Self, Error> {
+        let genesis = network.genesis_block_header();
+        let genesis_work = genesis
+            .target()
+            .map_err(|e| Error::InvalidHeader(genesis.block_hash(), e))?
+            .work();
+        let mut chain = Self {
+            network: network.clone(),
+            path: path.to_path_buf(),
+            hashes: vec![genesis.block_hash()],
+            chain_work: vec![genesis_work],
+            headers: vec![genesis],
+            persisted: 0,
+        };
+
+        let mut data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(chain),
+            Err(e) => return Err(Error::ReadHeadersFile(path.display().to_string(), e)),
+        };
+        if data.len() % BlockHeader::SIZE != 0 {
+            return Err(Error::TruncatedHeadersFile(path.display().to_string()));
+        }
+
+        let mut stored = data
+            .chunks_exact_mut(BlockHeader::SIZE)
+            .map(BlockHeader::deserialize)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::InvalidHeadersFile(path.display().to_string(), e))?;
+        if stored.is_empty() {
+            return Ok(chain);
+        }
+        if stored.remove(0) != chain.headers[0] {
+            return Err(Error::WrongGenesis(path.display().to_string()));
+        }
+
+        chain.connect(&stored)?;
+        chain.persisted = chain.headers.len();
+        Ok(chain)
+    }
+
+    pub fn height(&self) -> usize {
+        self.headers.len() - 1
+    }
+
+    pub fn tip(&self) -> &Hash256 {
+        &self.hashes[self.height()]
+    }
+
+    pub fn locator(&self) -> Vec<Hash256> {
+        block_locator(&self.hashes)
+    }
+
+    /// Validates the headers and adds them to the chain, returning how many were added.
+    ///
+    /// The headers may fork from any block of the chain, the branch replaces the current tip only
+    /// if it accumulates more work, so a longer branch mined at a lower difficulty is ignored.
+    pub fn connect(&mut self, headers: &[BlockHeader]) -> Result<usize, Error> {
+        let Some(first) = headers.first() else {
+            return Ok(0);
+        };
+        let fork = self
+            .hashes
+            .iter()
+            .rposition(|hash| hash == first.prev_block_hash())
+            .ok_or_else(|| Error::DisconnectedHeader(first.block_hash()))?;
+
+        let mut prev_block_hash = *first.prev_block_hash();
+        let mut work = self.chain_work[fork];
+        let mut hashes = Vec::with_capacity(headers.len());
+        let mut chain_work = Vec::with_capacity(headers.len());
+        for header in headers {
+            let block_hash = header.block_hash();
+            if *header.prev_block_hash() != prev_block_hash {
+                return Err(Error::DisconnectedHeader(block_hash));
+            }
+            header
+                .check_proof_of_work(&self.network)
+                .map_err(|e| Error::InvalidHeader(block_hash, e))?;
+            let target = header
+                .target()
+                .map_err(|e| Error::InvalidHeader(block_hash, e))?;
+            work = work.saturating_add(&target.work());
+            hashes.push(block_hash);
+            chain_work.push(work);
+            prev_block_hash = block_hash;
+        }
+
+        // On a tie the branch seen first is kept
+        if work <= self.chain_work[self.height()] {
+            return Ok(0);
+        }
+
+        self.headers.truncate(fork + 1);
+        self.hashes.truncate(fork + 1);
+        self.chain_work.truncate(fork + 1);
+        self.persisted = self.persisted.min(fork + 1);
+        self.headers.extend_from_slice(headers);
+        self.hashes.extend(hashes);
+        self.chain_work.extend(chain_work);
+        Ok(headers.len())
+    }
+
+    /// Writes the headers added since the last save, rewriting the replaced ones after a fork
+    pub fn save(&mut self) -> Result<(), Error> {
+        let write_error = |e| Error::WriteHeadersFile(self.path.display().to_string(), e);
+
+        let mut data =
+            Vec::with_capacity((self.headers.len() - self.persisted) * BlockHeader::SIZE);
+        for header in &self.headers[self.persisted..] {
+            data.extend(header.serialize().map_err(Error::SerializeHeader)?);
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.path)
+            .map_err(write_error)?;
+        file.set_len((self.persisted * BlockHeader::SIZE) as u64)
+            .map_err(write_error)?;
+        file.seek(SeekFrom::End(0)).map_err(write_error)?;
+        file.write_all(&data).map_err(write_error)?;
+
+        self.persisted = self.headers.len();
+        Ok(())
+    }
+}
+
+/// Requests headers from the peer until it has nothing new to send, the chain is saved after
+/// every batch so the progress is kept if the connection drops.
+pub async fn run(
+    framed: &mut Framed<TcpStream, MessageCodec>,
+    addr: &SocketAddr,
+    network: &Network,
+    chain: &mut HeaderChain,
+) -> Result<(), Error> {
+    loop {
+        let get_headers = GetHeaders::new(PROTOCOL_VERSION as u32, chain.locator(), Hash256::ZERO);
+        let message = Message::build(
+            Payload::GetHeaders(get_headers),
+            MessageType::GetHeaders,
+            network,
+        );
+        framed.send(message).await.map_err(Error::SendGetHeaders)?;
+
+        let headers = timeout(HEADERS_TIMEOUT, receive_headers(framed, network))
+            .await
+            .map_err(Error::HeadersTimeout)??;
+        let added = chain.connect(&headers)?;
+        chain.save()?;
+        info!(
+            "{addr} sent {} headers, height {} with tip {}",
+            headers.len(),
+            chain.height(),
+            chain.tip()
+        );
+
+        // A full batch means the peer may have more
+        if added == 0 || (headers.len() as u64) < MAX_HEADERS_RESULTS {
+            return Ok(());
+        }
+    }
+}
+
+async fn receive_headers(
+    framed: &mut Framed<TcpStream, MessageCodec>,
+    network: &Network,
+) -> Result<Vec<BlockHeader>, Error> {
+    loop {
+        let message = framed
+            .next()
+            .await
+            .ok_or(Error::ConnectionClosed)?
+            .map_err(Error::Deserialize)?;
+        match message.payload() {
+            Payload::Headers(headers) => return Ok(headers.headers().clone()),
+            Payload::Ping(ping) => {
+                let pong = Pong::new(*ping.nonce());
+                framed
+                    .send(Message::build(
+                        Payload::Pong(pong),
+                        MessageType::Pong,
+                        network,
+                    ))
+                    .await
+                    .map_err(Error::SendPong)?;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to read the headers file {0}")]
+    ReadHeadersFile(String, #[source] std::io::Error),
+    #[error("Failed to write the headers file {0}")]
+    WriteHeadersFile(String, #[source] std::io::Error),
+    #[error("The headers file {0} ends with an incomplete header")]
+    TruncatedHeadersFile(String),
+    #[error("The headers file {0} contains an invalid header")]
+    InvalidHeadersFile(String, #[source] SerdeBitcoinError),
+    #[error("The headers file {0} belongs to another network")]
+    WrongGenesis(String),
+    #[error("Failed to serialize a header")]
+    SerializeHeader(#[source] SerdeBitcoinError),
+    #[error("Header {0} does not connect to the chain")]
+    DisconnectedHeader(Hash256),
+    #[error("Header {0} is invalid")]
+    InvalidHeader(Hash256, #[source] SerdeBitcoinError),
+    #[error("Failed to send the getheaders message")]
+    SendGetHeaders(#[source] SerdeBitcoinError),
+    #[error("Failed to send the pong message")]
+    SendPong(#[source] SerdeBitcoinError),
+    #[error("Failed to deserialize the message")]
+    Deserialize(#[source] SerdeBitcoinError),
+    #[error("Connection closed by the peer")]
+    ConnectionClosed,
+    #[error("Headers timeout")]
+    HeadersTimeout(#[source] Elapsed),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{sender, test_peer};
+    use bitcoin::codec::PayloadLimits;
+    use bitcoin::headers::Headers;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    /// Mines `length` regtest headers with the `bits` on top of the genesis block
+    fn mine_chain(length: usize, time_offset: u32, bits: u32) -> Vec<BlockHeader> {
+        let network = Network::Regtest;
+        let mut chain = vec![network.genesis_block_header()];
+        for _ in 0..length {
+            let prev = chain.last().expect("genesis");
+            let header = (0..)
+                .map(|nonce| {
+                    BlockHeader::new(
+                        4,
+                        prev.block_hash(),
+                        Hash256::ZERO,
+                        prev.time() + 600 + time_offset,
+                        bits,
+                        nonce,
+                    )
+                })
+                .find(|header| header.check_proof_of_work(&network).is_ok())
+                .expect("nonce");
+            chain.push(header);
+        }
+        chain
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("headers_{}_{name}.dat", std::process::id()))
+    }
+
+    /// Stand-in peer answering every getheaders from `chain`
+    async fn serve_headers(listener: TcpListener, chain: Vec<BlockHeader>) {
+        let network = Network::Regtest;
+        let mut framed = test_peer::accept(&listener, &network).await;
+        let hashes: Vec<Hash256> = chain.iter().map(BlockHeader::block_hash).collect();
+
+        while let Some(message) = framed.next().await {
+            let message = message.expect("valid message");
+            let Payload::GetHeaders(get_headers) = message.payload() else {
+                continue;
+            };
+            let start = get_headers
+                .locator_hashes()
+                .iter()
+                .find_map(|hash| hashes.iter().position(|known| known == hash))
+                .map_or(0, |position| position + 1);
+            let headers = chain[start..]
+                .iter()
+                .take(MAX_HEADERS_RESULTS as usize)
+                .cloned()
+                .collect();
+            let payload = Payload::Headers(Headers::new(headers));
+            test_peer::send(&mut framed, payload, MessageType::Headers, &network).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_headers() {
+        let network = Arc::new(Network::Regtest);
+        let served = mine_chain(2500, 0, Network::Regtest.pow_limit_bits());
+        let path = temp_path("sync");
+        let _ = fs::remove_file(&path);
+
+        // Serve the chain from a local stand-in peer
+        let (listener, addr) = test_peer::bind().await;
+        let peer = tokio::spawn(serve_headers(listener, served.clone()));
+
+        // Sync the whole chain, it takes more than one getheaders
+        let mut chain = HeaderChain::load(&path, &network).expect("load");
+        let (info, mut framed) = sender::run(
+            &addr,
+            network.clone(),
+            PayloadLimits::default(),
+            Arc::default(),
+            Arc::default(),
+        )
+        .await
+        .expect("handshake");
+        run(&mut framed, info.addr(), &network, &mut chain)
+            .await
+            .expect("sync");
+        drop(framed);
+        peer.await.expect("peer");
+
+        // Assert that the best chain is the served one
+        assert_eq!(chain.height(), 2500);
+        assert_eq!(*chain.tip(), served[2500].block_hash());
+
+        // Assert that the chain was persisted
+        let loaded = HeaderChain::load(&path, &network).expect("reload");
+        assert_eq!(loaded.height(), 2500);
+        assert_eq!(loaded.tip(), chain.tip());
+
+        fs::remove_file(&path).expect("remove");
+    }
+
+    #[test]
+    fn test_connect_fork() {
+        let path = temp_path("fork");
+        let _ = fs::remove_file(&path);
+        let mut chain = HeaderChain::load(&path, &Network::Regtest).expect("load");
+        let main = mine_chain(10, 0, Network::Regtest.pow_limit_bits());
+        let fork = mine_chain(12, 1, Network::Regtest.pow_limit_bits());
+
+        assert_eq!(chain.connect(&main[1..]).expect("connect"), 10);
+        chain.save().expect("save");
+
+        // A shorter branch is validated but ignored
+        assert_eq!(chain.connect(&fork[1..5]).expect("connect"), 0);
+        assert_eq!(*chain.tip(), main[10].block_hash());
+
+        // A longer branch replaces the tip and the file
+        assert_eq!(chain.connect(&fork[1..]).expect("connect"), 12);
+        chain.save().expect("save");
+        let loaded = HeaderChain::load(&path, &Network::Regtest).expect("reload");
+        assert_eq!(loaded.height(), 12);
+        assert_eq!(*loaded.tip(), fork[12].block_hash());
+
+        fs::remove_file(&path).expect("remove");
+    }
+
+    #[test]
+    fn test_connect_most_work() {
+        let mut chain = HeaderChain::load(&temp_path("work"), &Network::Regtest).expect("load");
+        let easy = mine_chain(10, 0, Network::Regtest.pow_limit_bits());
+        // Every header needs 128 times the work of one at the minimum difficulty
+        let hard = mine_chain(2, 1, 0x2000ffff);
+
+        assert_eq!(chain.connect(&easy[1..4]).expect("connect"), 3);
+
+        // A shorter branch with more work replaces the tip
+        assert_eq!(chain.connect(&hard[1..]).expect("connect"), 2);
+        assert_eq!(*chain.tip(), hard[2].block_hash());
+
+        // A longer branch with less work is ignored
+        assert_eq!(chain.connect(&easy[1..]).expect("connect"), 0);
+        assert_eq!(chain.height(), 2);
+        assert_eq!(*chain.tip(), hard[2].block_hash());
+    }
+
+    #[test]
+    fn test_connect_invalid_headers() {
+        let mut chain = HeaderChain::load(&temp_path("invalid"), &Network::Regtest).expect("load");
+        let headers = mine_chain(3, 0, Network::Regtest.pow_limit_bits());
+
+        // Headers that do not connect to the chain
+        assert!(matches!(
+            chain.connect(&headers[2..]),
+            Err(Error::DisconnectedHeader(_))
+        ));
+
+        // Headers that do not meet their target
+        let header = &headers[1];
+        let invalid = (0..)
+            .map(|nonce| {
+                BlockHeader::new(
+                    *header.version(),
+                    *header.prev_block_hash(),
+                    *header.merkle_root(),
+                    *header.time(),
+                    *header.bits(),
+                    nonce,
+                )
+            })
+            .find(|header| header.check_proof_of_work(&Network::Regtest).is_err())
+            .expect("nonce");
+        assert!(matches!(
+            chain.connect(&[invalid]),
+            Err(Error::InvalidHeader(..))
+        ));
+        assert_eq!(chain.height(), 0);
+    }
+}
@@ -0,0 +1,87 @@
+//! Stand-in peer playing the remote side of a connection on loopback
+
+use bitcoin::codec::{MessageCodec, PayloadLimits};
+use bitcoin::message_type::MessageType;
+use bitcoin::network::Network;
+use bitcoin::ping::Pong;
+use bitcoin::verack::VerAck;
+use bitcoin::version::VersionBuilder;
+use bitcoin::{Message, Payload};
+use futures::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::codec::Framed;
+
+pub async fn bind() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("local address");
+    (listener, addr)
+}
+
+/// Accepts a connection and answers the handshake started by the other side, including the
+/// ping that follows the verack
+pub async fn accept(listener: &TcpListener, network: &Network) -> Framed<TcpStream, MessageCodec> {
+    let (stream, addr) = listener.accept().await.expect("accept");
+    let local_addr = stream.local_addr().expect("local address");
+    let mut framed = Framed::new(stream, MessageCodec::new(network, PayloadLimits::default()));
+
+    loop {
+        let message = receive(&mut framed).await;
+        match message.payload() {
+            Payload::Version(_) => {
+                let version = VersionBuilder::default()
+                    .receiver_address(addr)
+                    .sender_address(local_addr)
+                    .build()
+                    .expect("version");
+                send(
+                    &mut framed,
+                    Payload::Version(version),
+                    MessageType::Version,
+                    network,
+                )
+                .await;
+            }
+            Payload::VerAck(_) => {
+                send(
+                    &mut framed,
+                    Payload::VerAck(VerAck),
+                    MessageType::VerAck,
+                    network,
+                )
+                .await;
+            }
+            Payload::Ping(ping) => {
+                send(
+                    &mut framed,
+                    Payload::Pong(Pong::new(*ping.nonce())),
+                    MessageType::Pong,
+                    network,
+                )
+                .await;
+                return framed;
+            }
+            _ => {}
+        }
+    }
+}
+
+pub async fn send(
+    framed: &mut Framed<TcpStream, MessageCodec>,
+    payload: Payload,
+    ty: MessageType,
+    network: &Network,
+) {
+    framed
+        .send(Message::build(payload, ty, network))
+        .await
+        .expect("send");
+}
+
+pub async fn receive(framed: &mut Framed<TcpStream, MessageCodec>) -> Message {
+    framed
+        .next()
+        .await
+        .expect("connection open")
+        .expect("valid message")
+}